@@ -0,0 +1,83 @@
+//! Example of async I2C
+//!
+//! Reads the PMIC device ID every second through the board PMIC, awaiting interrupt
+//! driven I2C1 transfers on the shared bus.
+//! Blue LED toggles on every successful read, red LED is on while reads fail.
+//!
+
+#![no_std]
+#![no_main]
+
+use defmt::{error, info};
+use portenta_h7::board::async_impl::{self as portenta, Board, LedBlue, LedRed, PmicImpl};
+use rtic::app;
+use rtic_monotonics::systick::prelude::*;
+
+systick_monotonic!(Mono, 1000);
+
+#[app(device = portenta_h7::hal::pac, peripherals = false, dispatchers = [SPI1])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        info!("Init");
+
+        // Get board resources
+        let Board {
+            led_red,
+            led_blue,
+            pmic,
+            clocks,
+            ..
+        } = Board::builder().usb(false).take();
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        info!("Spawning tasks");
+        let _ = read_pmic::spawn(pmic, led_red, led_blue);
+
+        (Shared {}, Local {})
+    }
+
+    #[task(priority = 1)]
+    async fn read_pmic(
+        _cx: read_pmic::Context,
+        mut pmic: PmicImpl,
+        mut led_red: LedRed,
+        mut led_blue: LedBlue,
+    ) {
+        loop {
+            match pmic.verify_device_id().await {
+                Ok(id) => {
+                    info!("PMIC device ID: {:X}", id);
+                    led_red.off();
+                    led_blue.toggle();
+                }
+                Err(err) => {
+                    error!("PMIC device ID error: {}", err);
+                    led_blue.off();
+                    led_red.on();
+                }
+            }
+            Mono::delay(1000.millis()).await;
+        }
+    }
+
+    #[task(priority = 2, binds = I2C1_EV)]
+    fn i2c1_ev(_cx: i2c1_ev::Context) {
+        portenta::on_i2c1_interrupt();
+    }
+
+    #[task(priority = 2, binds = I2C1_ER)]
+    fn i2c1_er(_cx: i2c1_er::Context) {
+        portenta::on_i2c1_interrupt();
+    }
+}
@@ -0,0 +1,68 @@
+//! Example of backup SRAM
+//!
+//! Counts boots in the backup SRAM and logs them with the reason of the last reset,
+//! blinking the green LED once per boot, up to 10 times. The board then resets itself,
+//! the count going on, until power is lost without VBAT.
+//!
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::SCB;
+use defmt::info;
+use portenta_h7::board::{Board, LedGreen};
+use rtic::app;
+use rtic_monotonics::systick::prelude::*;
+
+systick_monotonic!(Mono, 1000);
+
+const MAX_BLINKS: u32 = 10;
+
+#[app(device = portenta_h7::hal::pac, peripherals = false, dispatchers = [SPI1])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        info!("Init");
+
+        // Get board resources
+        let Board {
+            led_green,
+            mut backup,
+            reset_reason,
+            clocks,
+            ..
+        } = Board::builder().usb(false).take();
+
+        // Boot counter, kept in backup SRAM across resets
+        let boots = backup.update(|boots: &mut u32| *boots += 1);
+        info!("Boot {} after {} reset", boots, reset_reason);
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        let _ = blink_boots::spawn(led_green, boots);
+
+        (Shared {}, Local {})
+    }
+
+    #[task]
+    async fn blink_boots(_cx: blink_boots::Context, mut led: LedGreen, boots: u32) {
+        for _ in 0..boots.min(MAX_BLINKS) {
+            led.on();
+            Mono::delay(200.millis()).await;
+            led.off();
+            Mono::delay(300.millis()).await;
+        }
+        Mono::delay(1.secs()).await;
+        info!("Resetting");
+        SCB::sys_reset();
+    }
+}
@@ -1,15 +1,15 @@
 //! Example of blinky
 //!
-//! Toggles the 3 user LEDs with a different frequency
+//! Plays a different pattern on each of the 3 user LEDs
 //!
 
 #![no_std]
 #![no_main]
 
 use defmt::info;
-use portenta_h7::board::{
-    self,
-    non_async_impl::{Board, LedBlue, LedGreen, LedRed},
+use portenta_h7::{
+    board::{Board, LedBlue, LedGreen, LedRed},
+    drivers::led::{Dimmable, Pattern, Sequencer},
 };
 use rtic::app;
 use rtic_monotonics::systick::prelude::*;
@@ -30,15 +30,17 @@ mod app {
     fn init(cx: init::Context) -> (Shared, Local) {
         info!("Init");
 
-        Mono::start(cx.core.SYST, board::CORE_FREQUENCY.raw());
-
         // Get board resources
         let Board {
             led_red,
             led_green,
             led_blue,
+            clocks,
             ..
-        } = Board::take();
+        } = Board::builder().usb(false).take();
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
 
         #[cfg(debug_assertions)]
         info!("spawning tasks");
@@ -50,26 +52,24 @@ mod app {
     }
 
     #[task]
-    async fn blink_led_red(_cx: blink_led_red::Context, mut led: LedRed) {
-        loop {
-            led.toggle();
-            Mono::delay(500.millis()).await;
-        }
+    async fn blink_led_red(_cx: blink_led_red::Context, led: LedRed) {
+        play(Sequencer::new(led, Pattern::Blink(1))).await
     }
 
     #[task]
-    async fn blink_led_green(_cx: blink_led_green::Context, mut led: LedGreen) {
-        loop {
-            led.toggle();
-            Mono::delay(1000.millis()).await;
-        }
+    async fn blink_led_green(_cx: blink_led_green::Context, led: LedGreen) {
+        play(Sequencer::new(led, Pattern::Heartbeat)).await
     }
 
     #[task]
-    async fn blink_led_blue(_cx: blink_led_blue::Context, mut led: LedBlue) {
-        loop {
-            led.toggle();
-            Mono::delay(2000.millis()).await;
-        }
+    async fn blink_led_blue(_cx: blink_led_blue::Context, led: LedBlue) {
+        play(Sequencer::new(led, Pattern::Sos)).await
+    }
+}
+
+async fn play(mut sequencer: Sequencer<impl Dimmable>) -> ! {
+    loop {
+        let next = sequencer.poll(Mono::now().duration_since_epoch());
+        Mono::delay(next).await;
     }
 }
@@ -0,0 +1,90 @@
+//! Example of RGB LED
+//!
+//! Mixes the 3 user LEDs into a single RGB LED dimmed by software PWM, ticked by
+//! the TIM7 interrupt once the first color is set, and cycles through a few colors
+//!
+
+#![no_std]
+#![no_main]
+
+use defmt::info;
+use portenta_h7::{
+    board::{Board, LedPwmTimer, RgbLedImpl},
+    drivers::led::{Rgb, RgbLed},
+};
+use rtic::app;
+use rtic_monotonics::systick::prelude::*;
+
+systick_monotonic!(Mono, 1000);
+
+const COLORS: [Rgb; 6] = [
+    Rgb::RED,
+    Rgb::ORANGE,
+    Rgb(255, 128, 0).scaled(32),
+    Rgb::CYAN,
+    Rgb(64, 0, 128),
+    Rgb::WHITE,
+];
+
+#[app(device = portenta_h7::hal::pac, peripherals = false, dispatchers = [SPI1])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        rgb_led: RgbLedImpl,
+        led_pwm_timer: LedPwmTimer,
+    }
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        info!("Init");
+
+        // Get board resources
+        let Board {
+            led_red,
+            led_green,
+            led_blue,
+            led_pwm_timer,
+            clocks,
+            ..
+        } = Board::builder().usb(false).take();
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        let rgb_led = RgbLed::new(led_red, led_green, led_blue);
+
+        #[cfg(debug_assertions)]
+        info!("spawning tasks");
+        let _ = cycle_colors::spawn();
+
+        (
+            Shared {
+                rgb_led,
+                led_pwm_timer,
+            },
+            Local {},
+        )
+    }
+
+    #[task(shared = [rgb_led, led_pwm_timer])]
+    async fn cycle_colors(mut cx: cycle_colors::Context) {
+        for color in COLORS.iter().cycle() {
+            info!("Color: {}", color);
+            cx.shared.rgb_led.lock(|rgb_led| rgb_led.set_color(*color));
+            // Ticks the PWM from the first color on
+            cx.shared.led_pwm_timer.lock(|timer| timer.enable());
+            Mono::delay(1000.millis()).await;
+        }
+    }
+
+    #[task(priority = 2, binds = TIM7, shared = [rgb_led, led_pwm_timer])]
+    fn led_pwm_tick(mut cx: led_pwm_tick::Context) {
+        cx.shared.led_pwm_timer.lock(|timer| timer.clear_irq());
+        cx.shared.rgb_led.lock(|rgb_led| rgb_led.tick());
+    }
+}
@@ -0,0 +1,162 @@
+//! Example SDRAM test over USB
+//!
+//! Sets up the device to appear as a virtual serial port to the host, e.g. for
+//! manufacturing tests. When the host sends `q` the device runs the quick SDRAM test,
+//! when it sends `f` the full one, and replies with a line such as
+//! `pass` or `fail 3103 bits 00000020 lines 00000000`.
+//!
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use defmt::{error, info, warn};
+use portenta_h7::board::{
+    sdram::{self_test, Coverage, Sdram},
+    Board, UsbBusImpl,
+};
+use rtic::app;
+use rtic_monotonics::systick::prelude::*;
+use rtic_sync::{channel::*, make_channel};
+use static_cell::StaticCell;
+use usb_device::{class_prelude::UsbBusAllocator, prelude::*};
+use usbd_serial::CdcAcmClass;
+
+systick_monotonic!(Mono, 1000);
+
+const CHANNEL_CAPACITY: usize = 1;
+type Msg = Coverage;
+
+const USB_MAX_PACKET_SIZE: usize = 64;
+const USB_BUS_BUFFER_SIZE: usize = 1024;
+
+/// Reply line, formatted in place
+struct Reply {
+    buf: [u8; USB_MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Write for Reply {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[app(device = portenta_h7::hal::pac, peripherals = false)]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        usb_serial_port: CdcAcmClass<'static, UsbBusImpl>,
+    }
+
+    #[local]
+    struct Local {
+        usb_dev: UsbDevice<'static, UsbBusImpl>,
+        sender: Sender<'static, Msg, CHANNEL_CAPACITY>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        info!("Init");
+
+        // Get board resources
+        let Board {
+            usb, sdram, clocks, ..
+        } = Board::take();
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        // Init USB stack
+        static USB_ALLOCATOR: StaticCell<UsbBusAllocator<UsbBusImpl>> = StaticCell::new();
+        let usb_bus = USB_ALLOCATOR.init(UsbBusImpl::new(
+            usb.unwrap(),
+            portenta_h7::ram_static!(AxiSram, [u32; USB_BUS_BUFFER_SIZE], zeroed),
+        ));
+        let usb_serial_port = usbd_serial::CdcAcmClass::new(usb_bus, USB_MAX_PACKET_SIZE as u16);
+        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1234, 0xABCD))
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .max_packet_size_0(64)
+            .unwrap()
+            .strings(&[StringDescriptors::default()
+                .manufacturer("example")
+                .product("sdram-test")
+                .serial_number("0123456789ABCDEF")])
+            .unwrap()
+            .build();
+
+        // Create a channel to communicate between tasks
+        let (sender, receiver) = make_channel!(Msg, CHANNEL_CAPACITY);
+
+        info!("Spawning tasks");
+        let _ = sdram_test::spawn(sdram.unwrap(), receiver);
+
+        (Shared { usb_serial_port }, Local { usb_dev, sender })
+    }
+
+    /// Runs the requested tests, preempted by the USB interrupt so the device keeps
+    /// answering the host meanwhile
+    #[task(priority = 0, shared = [usb_serial_port])]
+    async fn sdram_test(
+        mut cx: sdram_test::Context,
+        mut sdram: Sdram,
+        mut receiver: Receiver<'static, Msg, CHANNEL_CAPACITY>,
+    ) {
+        while let Ok(coverage) = receiver.recv().await {
+            info!("Testing {}", coverage);
+            // Nothing is placed in the SDRAM by this example
+            let report = unsafe { self_test(&mut sdram, coverage) };
+            info!("{}", report);
+
+            let mut reply = Reply {
+                buf: [0; USB_MAX_PACKET_SIZE],
+                len: 0,
+            };
+            let _ = if report.passed() {
+                writeln!(reply, "pass\r")
+            } else {
+                writeln!(
+                    reply,
+                    "fail {} bits {:08x} lines {:08x}\r",
+                    report.failure_count, report.failing_bits, report.failing_address_lines
+                )
+            };
+            cx.shared.usb_serial_port.lock(|usb_serial_port| {
+                if let Err(err) = usb_serial_port.write_packet(&reply.buf[..reply.len]) {
+                    error!("Error in transmission: {:?}", err as u8)
+                }
+            });
+        }
+    }
+
+    #[task(priority = 1, binds = OTG_HS, local = [usb_dev, sender], shared = [usb_serial_port])]
+    fn usb_process(mut cx: usb_process::Context) {
+        let usb_dev = cx.local.usb_dev;
+        let sender = cx.local.sender;
+        cx.shared.usb_serial_port.lock(|usb_serial_port| {
+            if usb_dev.poll(&mut [usb_serial_port]) {
+                let mut app_buff = [0u8; USB_MAX_PACKET_SIZE];
+                if let Ok(cnt) = usb_serial_port.read_packet(&mut app_buff) {
+                    for command in &app_buff[..cnt] {
+                        let coverage = match command {
+                            b'q' => Coverage::QUICK,
+                            b'f' => Coverage::FULL,
+                            _ => continue,
+                        };
+                        if sender.try_send(coverage).is_err() {
+                            warn!("Test already running");
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
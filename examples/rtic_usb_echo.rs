@@ -9,9 +9,9 @@
 #![no_main]
 
 use defmt::{error, info};
-use portenta_h7::board::{
-    self,
-    non_async_impl::{Board, LedGreen, LedRed, UsbBusImpl},
+use portenta_h7::{
+    board::{Board, LedGreen, LedRed, UsbBusImpl},
+    itcm, sys,
 };
 use rtic::app;
 use rtic_monotonics::systick::prelude::*;
@@ -52,22 +52,29 @@ mod app {
     fn init(cx: init::Context) -> (Shared, Local) {
         info!("Init");
 
-        Mono::start(cx.core.SYST, board::CORE_FREQUENCY.raw());
-
         // Get board resources
         let Board {
             led_red,
             led_green,
             usb,
+            clocks,
             ..
         } = Board::take();
 
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        // The USB interrupt handler was linked in ITCM
+        info!("usb_echo at {}", usb_echo as *const ());
+        assert!(sys::itcm::runs_from_itcm(usb_echo as *const ()));
+
         // Init USB stack
-        static USB_BUS_BUFFER: StaticCell<[u32; USB_BUS_BUFFER_SIZE]> = StaticCell::new();
         static USB_ALLOCATOR: StaticCell<UsbBusAllocator<UsbBusImpl>> = StaticCell::new();
         let usb_bus = USB_ALLOCATOR.init(UsbBusImpl::new(
-            usb,
-            USB_BUS_BUFFER.init([0; USB_BUS_BUFFER_SIZE]),
+            usb.unwrap(),
+            // Endpoint memory, copied through the OTG_HS FIFOs by the CPU as the
+            // core runs in slave mode, in AXI SRAM to leave DTCM to the stack
+            portenta_h7::ram_static!(AxiSram, [u32; USB_BUS_BUFFER_SIZE], zeroed),
         ));
         let usb_serial_port = usbd_serial::CdcAcmClass::new(usb_bus, USB_MAX_PACKET_SIZE as u16);
         let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1234, 0xABCD))
@@ -132,7 +139,18 @@ mod app {
 
     #[task(priority = 1, binds = OTG_HS, local = [usb_dev, usb_serial_port, sender])]
     fn usb_process(cx: usb_process::Context) {
-        let (usb_dev, usb_serial_port) = (cx.local.usb_dev, cx.local.usb_serial_port);
+        usb_echo(cx.local.usb_dev, cx.local.usb_serial_port, cx.local.sender);
+    }
+}
+
+itcm! {
+    /// Polls the USB device and echoes what it received, run from ITCM to lower the
+    /// interrupt latency
+    fn usb_echo(
+        usb_dev: &mut UsbDevice<'static, UsbBusImpl>,
+        usb_serial_port: &mut CdcAcmClass<'static, UsbBusImpl>,
+        sender: &mut Sender<'static, Msg, CHANNEL_CAPACITY>,
+    ) {
         let previous_state = usb_dev.state();
 
         // Trigger internal state machine. It should be called either from ISR on USB event,
@@ -164,7 +182,7 @@ mod app {
         match usb_dev.state() {
             // Enumeration complete
             UsbDeviceState::Configured if previous_state == UsbDeviceState::Addressed => {
-                let _ = cx.local.sender.try_send(EnumerationState::Complete);
+                let _ = sender.try_send(EnumerationState::Complete);
             }
 
             // Enumeration lost
@@ -172,7 +190,7 @@ mod app {
                 if previous_state == UsbDeviceState::Configured
                     && state != UsbDeviceState::Configured =>
             {
-                let _ = cx.local.sender.try_send(EnumerationState::Lost);
+                let _ = sender.try_send(EnumerationState::Lost);
             }
             _ => (),
         }
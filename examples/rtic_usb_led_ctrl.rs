@@ -11,9 +11,9 @@
 
 use core::mem::size_of;
 use defmt::{debug, error, info};
-use portenta_h7::board::{
-    self,
-    non_async_impl::{Board, LedBlue, LedGreen, LedRed, UsbBusImpl},
+use portenta_h7::{
+    board::{Board, LedBlue, LedGreen, LedRed, UsbBusImpl},
+    itcm, sys,
 };
 use rtic::app;
 use rtic_monotonics::systick::prelude::*;
@@ -91,22 +91,30 @@ mod app {
     #[init]
     fn init(cx: init::Context) -> (Shared, Local) {
         info!("Init");
-        Mono::start(cx.core.SYST, board::CORE_FREQUENCY.raw());
         // Get board resources
         let Board {
             led_red,
             led_green,
             led_blue,
             usb,
+            clocks,
             ..
         } = Board::take();
 
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        // The USB interrupt handler was linked in ITCM
+        info!("usb_receive at {}", usb_receive as *const ());
+        assert!(sys::itcm::runs_from_itcm(usb_receive as *const ()));
+
         // Init USB stack
-        static USB_BUS_BUFFER: StaticCell<[u32; USB_BUS_BUFFER_SIZE]> = StaticCell::new();
         static USB_ALLOCATOR: StaticCell<UsbBusAllocator<UsbBusImpl>> = StaticCell::new();
         let usb_bus = USB_ALLOCATOR.init(UsbBusImpl::new(
-            usb,
-            USB_BUS_BUFFER.init([0; USB_BUS_BUFFER_SIZE]),
+            usb.unwrap(),
+            // Endpoint memory, copied through the OTG_HS FIFOs by the CPU as the
+            // core runs in slave mode, in AXI SRAM to leave DTCM to the stack
+            portenta_h7::ram_static!(AxiSram, [u32; USB_BUS_BUFFER_SIZE], zeroed),
         ));
         let usb_serial_port = usbd_serial::CdcAcmClass::new(usb_bus, USB_MAX_PACKET_SIZE as u16);
         let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1234, 0xABCD))
@@ -171,9 +179,18 @@ mod app {
 
     #[task(priority = 1, binds = OTG_HS, local = [usb_dev, usb_serial_port, sender])]
     fn usb_process(cx: usb_process::Context) {
-        let (usb_dev, usb_serial_port, sender) =
-            (cx.local.usb_dev, cx.local.usb_serial_port, cx.local.sender);
+        usb_receive(cx.local.usb_dev, cx.local.usb_serial_port, cx.local.sender);
+    }
+}
 
+itcm! {
+    /// Polls the USB device and forwards the received commands, run from ITCM to
+    /// lower the interrupt latency
+    fn usb_receive(
+        usb_dev: &mut UsbDevice<'static, UsbBusImpl>,
+        usb_serial_port: &mut CdcAcmClass<'static, UsbBusImpl>,
+        sender: &mut Sender<'static, Msg, CHANNEL_CAPACITY>,
+    ) {
         // Check if there are events to process by CDC class
         if usb_dev.poll(&mut [usb_serial_port]) {
             let mut app_buff = [0u8; USB_MAX_PACKET_SIZE];
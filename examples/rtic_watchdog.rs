@@ -0,0 +1,99 @@
+//! Example of watchdog
+//!
+//! Two tasks check in with the watchdog, which a supervisor task feeds while both are on time.
+//! The second task hangs after a few check-ins, so the watchdog resets the board and reports it
+//! as the starving client on the next boot, with the red LED on.
+//!
+
+#![no_std]
+#![no_main]
+
+use defmt::{info, warn};
+use portenta_h7::board::{Board, CheckIn, LedGreen, Watchdog};
+use rtic::app;
+use rtic_monotonics::systick::prelude::*;
+
+systick_monotonic!(Mono, 1000);
+
+const WATCHDOG_TIMEOUT_MS: u32 = 1000;
+const SUPERVISOR_PERIOD_MS: u32 = 200;
+const HANG_AFTER_CHECK_INS: u32 = 10;
+
+#[app(device = portenta_h7::hal::pac, peripherals = false, dispatchers = [SPI1, SPI2])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        info!("Init");
+
+        // Get board resources, the watchdog started
+        let Board {
+            mut led_red,
+            led_green,
+            reset_reason,
+            mut watchdog,
+            clocks,
+            ..
+        } = Board::builder()
+            .usb(false)
+            .watchdog(WATCHDOG_TIMEOUT_MS.millis())
+            .take();
+
+        // Start after the board, which resets SysTick
+        Mono::start(cx.core.SYST, clocks.sys_ck.raw());
+
+        info!("Reset reason: {}", reset_reason);
+        if let Some(client) = watchdog.starved() {
+            warn!("Watchdog starved by client {}", client);
+            led_red.on();
+        }
+
+        let now = Mono::now().duration_since_epoch();
+        let blinker = watchdog.register(300.millis(), now).unwrap();
+        let worker = watchdog.register(1000.millis(), now).unwrap();
+        info!("Blinker {}, worker {}", blinker.id(), worker.id());
+
+        let _ = supervise::spawn(watchdog);
+        let _ = blink::spawn(led_green, blinker);
+        let _ = work::spawn(worker);
+
+        (Shared {}, Local {})
+    }
+
+    #[task(priority = 2)]
+    async fn supervise(_cx: supervise::Context, mut watchdog: Watchdog) {
+        loop {
+            if let Err(err) = watchdog.poll(Mono::now().duration_since_epoch()) {
+                warn!("Watchdog not fed: {}", err);
+            }
+            Mono::delay(SUPERVISOR_PERIOD_MS.millis()).await;
+        }
+    }
+
+    #[task]
+    async fn blink(_cx: blink::Context, mut led: LedGreen, check_in: CheckIn) {
+        loop {
+            led.toggle();
+            check_in.check_in(Mono::now().duration_since_epoch());
+            Mono::delay(100.millis()).await;
+        }
+    }
+
+    #[task]
+    async fn work(_cx: work::Context, check_in: CheckIn) {
+        for count in 1..=HANG_AFTER_CHECK_INS {
+            check_in.check_in(Mono::now().duration_since_epoch());
+            info!("Worker check-in {}", count);
+            Mono::delay(500.millis()).await;
+        }
+        warn!("Worker hanging");
+        core::future::pending::<()>().await;
+    }
+}
@@ -9,6 +9,8 @@ MEMORY
   SRAM4   : ORIGIN = 0x38000000, LENGTH = 64K
   BSRAM   : ORIGIN = 0x38800000, LENGTH = 4K
   ITCM    : ORIGIN = 0x00000000, LENGTH = 64K
+  SDRAM   : ORIGIN = 0xC0000000, LENGTH = 8M
+  QSPI    : ORIGIN = 0x90000000, LENGTH = 16M
 }
 
 SECTIONS {
@@ -32,6 +34,42 @@ SECTIONS {
     *(.sram4 .sram4.*);
     . = ALIGN(4);
     } > SRAM4
+  /* Backup value, watchdog and HSE failure records at fixed offsets, reserved even
+     when the firmware does not use them, so they stay in place across firmware
+     updates */
+  .bsram (NOLOAD) : ALIGN(4) {
+    KEEP(*(.bsram.backup));
+    . = ORIGIN(BSRAM) + 0x810;
+    KEEP(*(.bsram.watchdog));
+    . = ORIGIN(BSRAM) + 0x818;
+    KEEP(*(.bsram.css));
+    . = ORIGIN(BSRAM) + 0x820;
+    *(.bsram .bsram.*);
+    . = ALIGN(4);
+    } > BSRAM
+  .sdram (NOLOAD) : ALIGN(8) {
+    *(.sdram .sdram.*);
+    . = ALIGN(8);
+    __esdram = .;
+    } > SDRAM
+  /* Read-only data in the QSPI flash, read once the flash is memory-mapped. NOLOAD so
+     the probe and the -bin aliases leave it out, qspi-image.x makes it loadable to
+     extract the image programmed into the flash */
+  .qspi (NOLOAD) : ALIGN(4) {
+    *(.qspi .qspi.*);
+    . = ALIGN(4);
+    } > QSPI
 };
 
+/* Code copied from flash to ITCM at startup, kept off the null address */
+SECTIONS {
+  .itcm ORIGIN(ITCM) + 8 : ALIGN(4) {
+    __sitcm = .;
+    *(.itcm .itcm.*);
+    . = ALIGN(4);
+    __eitcm = .;
+    } > ITCM AT>FLASH
+  __siitcm = LOADADDR(.itcm);
+} INSERT AFTER .rodata;
+
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
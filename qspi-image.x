@@ -0,0 +1,8 @@
+/* Loadable .qspi, linked by the qspi-image alias to extract the content of the QSPI
+   flash, the firmware keeping it NOLOAD */
+OVERWRITE_SECTIONS {
+  .qspi : ALIGN(4) {
+    *(.qspi .qspi.*);
+    . = ALIGN(4);
+    } > QSPI
+}
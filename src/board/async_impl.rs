@@ -1 +1,106 @@
+//! board
+//!
+//! Async variant of the board, I2C1 transfers are interrupt driven and can be awaited
+//!
 
+use crate::board::{
+    clocks,
+    setup::{self, Resources},
+    Backup, BoardConfig, LedPwmTimer, Sdram, Watchdog,
+};
+use crate::drivers::pmic;
+use crate::sys::{ClockChangeListener, Clocks, ResetReason};
+use core::sync::atomic::{AtomicBool, Ordering};
+use rtic_sync::arbiter::{i2c::ArbiterDevice, Arbiter};
+use static_cell::StaticCell;
+
+pub use crate::board::i2c::{on_interrupt as on_i2c1_interrupt, AsyncI2c1};
+pub use setup::{LedBlue, LedGreen, LedRed, QspiFlashImpl, RgbLedImpl, UsbBusImpl, UsbPer};
+/// I2C1 bus, shared by creating [`I2c1Device`]s on it
+pub type I2c1Bus = Arbiter<AsyncI2c1>;
+pub type I2c1Device = ArbiterDevice<'static, AsyncI2c1>;
+pub type PmicImpl = pmic::AsyncPmic<I2c1Device>;
+
+pub struct Board {
+    pub led_red: LedRed,
+    pub led_green: LedGreen,
+    pub led_blue: LedBlue,
+    pub led_pwm_timer: LedPwmTimer,
+    pub usb: Option<UsbPer>,
+    pub pmic: PmicImpl,
+    pub i2c1: &'static I2c1Bus,
+    pub backup: Backup,
+    /// Cause of the reset before this boot
+    pub reset_reason: ResetReason,
+    pub watchdog: Watchdog,
+    pub sdram: Option<Sdram>,
+    pub qspi_flash: Option<QspiFlashImpl>,
+    /// Clocks frozen at setup, e.g. to start a SysTick monotonic
+    pub clocks: Clocks,
+}
+
+impl Board {
+    /// Brings up the board with the default configuration
+    pub fn take() -> Self {
+        Self::builder().take()
+    }
+
+    /// Starts a configuration to bring up the board with, see [`BoardConfig::take`]
+    pub fn builder() -> BoardConfig {
+        BoardConfig::new()
+    }
+
+    pub(crate) fn take_with(config: BoardConfig) -> Self {
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+        debug_assert!(!TAKEN.swap(true, Ordering::SeqCst));
+        Self::setup(&config)
+    }
+
+    fn setup(config: &BoardConfig) -> Self {
+        let Resources {
+            led_red,
+            led_green,
+            led_blue,
+            led_pwm_timer,
+            usb,
+            i2c1,
+            backup,
+            reset_reason,
+            watchdog,
+            sdram,
+            qspi_flash,
+            clocks,
+        } = setup::setup(config);
+
+        // I2C1 bus, shared between the PMIC and the application
+        static I2C1_BUS: StaticCell<I2c1Bus> = StaticCell::new();
+        let i2c1: &'static I2c1Bus = I2C1_BUS.init(Arbiter::new(AsyncI2c1::new(i2c1)));
+
+        Board {
+            led_red,
+            led_green,
+            led_blue,
+            led_pwm_timer,
+            usb,
+            pmic: pmic::AsyncPmic::new(ArbiterDevice::new(i2c1)),
+            i2c1,
+            backup,
+            reset_reason,
+            watchdog,
+            sdram,
+            qspi_flash,
+            clocks,
+        }
+    }
+}
+
+impl ClockChangeListener for &I2c1Bus {
+    /// Must not be called while a transfer is awaited
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        // Hold the bus so no transfer is in progress
+        let _i2c1 = self
+            .try_access()
+            .expect("I2C1 transfer in progress during a clock change");
+        clocks::retime_i2c1(clocks);
+    }
+}
@@ -0,0 +1,161 @@
+//! backup
+//!
+//! Storage in the 4K backup SRAM, retained by the backup regulator across resets and,
+//! with VBAT, power loss
+//!
+
+use crate::sys::cache;
+use core::{mem::MaybeUninit, ptr};
+use stm32h7xx_hal::pac;
+
+/// Bytes available to the stored value
+pub const BACKUP_CAPACITY: usize = 2048;
+
+const MAGIC: u32 = 0xBAC4_5A7E;
+const HEADER_WORDS: usize = 4;
+
+/// Bytes reserved for [`STORAGE`] at the start of the backup SRAM by memory.x
+const STORAGE_SIZE: usize = 0x810;
+
+/// Header and value, at the start of the backup SRAM so it stays in place across
+/// firmware updates
+#[link_section = ".bsram.backup"]
+static mut STORAGE: MaybeUninit<[u32; HEADER_WORDS + BACKUP_CAPACITY / 4]> = MaybeUninit::uninit();
+const _: () =
+    assert!(core::mem::size_of::<[u32; HEADER_WORDS + BACKUP_CAPACITY / 4]>() == STORAGE_SIZE);
+
+/// Value stored as its bytes in the backup SRAM
+///
+/// Bump `VERSION` when the layout changes, so values stored by a previous firmware
+/// are discarded rather than misread
+///
+/// # Safety
+///
+/// The type must have no padding nor pointers, and be valid for any bytes, e.g. a
+/// `#[repr(C)]` struct of integers and arrays of integers
+pub unsafe trait Persistent: Copy {
+    const VERSION: u32;
+}
+
+macro_rules! impl_persistent {
+    ($($ty:ty),*) => {
+        $(unsafe impl Persistent for $ty {
+            const VERSION: u32 = 0;
+        })*
+    };
+}
+
+impl_persistent!(u8, u16, u32, u64, i8, i16, i32, i64);
+unsafe impl<T: Persistent, const N: usize> Persistent for [T; N] {
+    const VERSION: u32 = T::VERSION;
+}
+
+/// Versioned, CRC-checked value in the backup SRAM
+///
+/// The value is found at the start of the backup SRAM by the layout of memory.x,
+/// every firmware run on the board, e.g. after an update, must keep the same layout
+/// and [`Persistent::VERSION`] to read it back.
+pub struct Backup {
+    _private: (),
+}
+
+impl Backup {
+    /// Unlocks the backup domain and enables the backup regulator retention
+    pub(crate) fn new() -> Self {
+        let pwr = unsafe { &(*pac::PWR::ptr()) };
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        pwr.cr1.modify(|_, w| w.dbp().set_bit());
+        rcc.ahb4enr.modify(|_, w| w.bkpramen().set_bit());
+        pwr.cr2.modify(|_, w| w.bren().set_bit());
+        while pwr.cr2.read().brrdy().bit_is_clear() {}
+        Self { _private: () }
+    }
+
+    /// Stored value, `None` when nothing valid was stored with the same version
+    pub fn load<T: Persistent>(&self) -> Option<T> {
+        const { assert!(core::mem::size_of::<T>() <= BACKUP_CAPACITY) };
+        let len = core::mem::size_of::<T>();
+        let header = Self::header();
+        if header[0] != MAGIC || header[1] != T::VERSION || header[2] != len as u32 {
+            return None;
+        }
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = value.as_mut_ptr() as *mut u8;
+        for (index, byte) in Self::data().take(len).enumerate() {
+            unsafe { bytes.add(index).write(byte) };
+        }
+        let crc = crc32(
+            unsafe { core::slice::from_raw_parts(bytes, len) },
+            T::VERSION,
+        );
+        (crc == header[3]).then(|| unsafe { value.assume_init() })
+    }
+
+    /// Stores `value`, replacing the previous one
+    pub fn store<T: Persistent>(&mut self, value: &T) {
+        const { assert!(core::mem::size_of::<T>() <= BACKUP_CAPACITY) };
+        let len = core::mem::size_of::<T>();
+        let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, len) };
+        // Invalidate first, so a reset while writing leaves nothing valid
+        Self::write_word(0, 0);
+        for (index, chunk) in bytes.chunks(4).enumerate() {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            Self::write_word(HEADER_WORDS + index, u32::from_le_bytes(word));
+        }
+        Self::write_word(1, T::VERSION);
+        Self::write_word(2, len as u32);
+        Self::write_word(3, crc32(bytes, T::VERSION));
+        Self::write_word(0, MAGIC);
+        Self::clean();
+    }
+
+    /// Loads the value, or its default, applies `f` to it and stores it
+    pub fn update<T: Persistent + Default>(&mut self, f: impl FnOnce(&mut T)) -> T {
+        let mut value = self.load().unwrap_or_default();
+        f(&mut value);
+        self.store(&value);
+        value
+    }
+
+    pub fn clear(&mut self) {
+        Self::write_word(0, 0);
+        Self::clean();
+    }
+
+    fn words() -> *mut u32 {
+        ptr::addr_of_mut!(STORAGE) as *mut u32
+    }
+
+    fn header() -> [u32; HEADER_WORDS] {
+        core::array::from_fn(|index| unsafe { Self::words().add(index).read_volatile() })
+    }
+
+    fn data() -> impl Iterator<Item = u8> {
+        (HEADER_WORDS..HEADER_WORDS + BACKUP_CAPACITY / 4)
+            .flat_map(|index| unsafe { Self::words().add(index).read_volatile() }.to_le_bytes())
+    }
+
+    fn write_word(index: usize, word: u32) {
+        unsafe { Self::words().add(index).write_volatile(word) };
+    }
+
+    /// Writes the cached storage to the backup SRAM, which would otherwise lose it
+    /// on reset
+    fn clean() {
+        cache::clean_dcache(unsafe { &*ptr::addr_of!(STORAGE) });
+    }
+}
+
+/// CRC-32 (IEEE) of `version` and `bytes`
+fn crc32(bytes: &[u8], version: u32) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in version.to_le_bytes().iter().chain(bytes) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,93 @@
+//! clocks
+//!
+//! Reconfiguration of the board peripherals when the clock profile changes,
+//! see [`crate::sys::switch_profile`]
+//!
+
+use crate::board::{LedPwmTimer, LED_PWM_TICK_FREQUENCY};
+use crate::hal::pac;
+use crate::sys::{ClockChangeListener, Clocks};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// SCL frequency of I2C1, kept to recompute its timings
+static I2C1_FREQUENCY: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn set_i2c1_frequency(frequency: crate::board::HertzU32) {
+    I2C1_FREQUENCY.store(frequency.raw(), Ordering::Relaxed);
+}
+
+/// Reprograms the I2C1 timings for the new APB1 clock, no transfer may be in progress
+pub(crate) fn retime_i2c1(clocks: &Clocks) {
+    let i2c = unsafe { &(*pac::I2C1::ptr()) };
+    let (presc, scll, sclh, sdadel, scldel) =
+        i2c_timing(clocks.pclk1.raw(), I2C1_FREQUENCY.load(Ordering::Relaxed));
+    i2c.cr1.modify(|_, w| w.pe().clear_bit());
+    i2c.timingr.write(|w| {
+        w.presc()
+            .bits(presc)
+            .scll()
+            .bits(scll)
+            .sclh()
+            .bits(sclh)
+            .sdadel()
+            .bits(sdadel)
+            .scldel()
+            .bits(scldel)
+    });
+    i2c.cr1.modify(|_, w| w.pe().set_bit());
+}
+
+/// I2C (PRESC, SCLL, SCLH, SDADEL, SCLDEL), same method as the HAL uses at setup
+fn i2c_timing(ker_ck: u32, frequency: u32) -> (u8, u8, u8, u8, u8) {
+    let ratio = ker_ck / frequency;
+    if frequency > 100_000 {
+        // Fast-mode (plus), SCLL + 1 = 2 * (SCLH + 1)
+        let presc_reg = (ratio - 1) / 384;
+        let presc = presc_reg + 1;
+        let sclh = ((ratio / presc) - 3) / 3;
+        let scll = 2 * (sclh + 1) - 1;
+        let (sdadel, scldel) = if frequency > 400_000 {
+            (0, (ker_ck / 4_000_000 / presc).max(1))
+        } else {
+            (
+                ker_ck / 8_000_000 / presc,
+                (ker_ck / 4_000_000 / presc).saturating_sub(1),
+            )
+        };
+        (
+            presc_reg as u8,
+            scll as u8,
+            sclh as u8,
+            sdadel as u8,
+            scldel as u8,
+        )
+    } else {
+        // Standard-mode, SCLL = SCLH
+        let presc_reg = (ratio - 1) / 512;
+        let presc = presc_reg + 1;
+        let sclh = ((ratio / presc) - 2) / 2;
+        let sdadel = ker_ck / 2_000_000 / presc;
+        let scldel = (ker_ck / 800_000 / presc).saturating_sub(1);
+        (
+            presc_reg as u8,
+            sclh as u8,
+            sclh as u8,
+            sdadel as u8,
+            scldel as u8,
+        )
+    }
+}
+
+impl ClockChangeListener for LedPwmTimer {
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        let tim = unsafe { &(*pac::TIM7::ptr()) };
+        let ticks = clocks.timx_ker_ck.raw() / LED_PWM_TICK_FREQUENCY.raw();
+        let psc = (ticks - 1) / (1 << 16);
+        let arr = ticks / (psc + 1) - 1;
+        tim.psc.write(|w| w.psc().bits(psc as u16));
+        tim.arr.write(|w| unsafe { w.bits(arr) });
+        // Load the new period without raising an update interrupt
+        tim.cr1.modify(|_, w| w.urs().counter_only());
+        tim.egr.write(|w| w.ug().set_bit());
+    }
+}
@@ -0,0 +1,198 @@
+//! compat
+//!
+//! Exposes HAL types, which implement `embedded-hal` 0.2, through the `embedded-hal` 1.0
+//! traits expected by the drivers
+//!
+
+use crate::board::setup::I2c1;
+use crate::hal;
+use core::convert::Infallible;
+use embedded_hal_v0::{blocking::i2c as i2c_v0, digital::v2 as digital_v0};
+use embedded_hal_v1::{digital, i2c};
+
+pub struct Compat<T>(T);
+
+impl<T> Compat<T> {
+    pub fn new(inner: T) -> Self {
+        Self(inner)
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.0
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: digital_v0::OutputPin<Error = Infallible>> digital::ErrorType for Compat<T> {
+    type Error = Infallible;
+}
+
+impl<T: digital_v0::OutputPin<Error = Infallible>> digital::OutputPin for Compat<T> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high()
+    }
+}
+
+impl<T: digital_v0::StatefulOutputPin<Error = Infallible>> digital::StatefulOutputPin
+    for Compat<T>
+{
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        digital_v0::StatefulOutputPin::is_set_high(&self.0)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        digital_v0::StatefulOutputPin::is_set_low(&self.0)
+    }
+}
+
+fn i2c_error_kind(error: hal::i2c::Error) -> i2c::ErrorKind {
+    match error {
+        hal::i2c::Error::NotAcknowledge => {
+            i2c::ErrorKind::NoAcknowledge(i2c::NoAcknowledgeSource::Unknown)
+        }
+        hal::i2c::Error::Arbitration => i2c::ErrorKind::ArbitrationLoss,
+        _ => i2c::ErrorKind::Bus,
+    }
+}
+
+impl i2c::ErrorType for Compat<I2c1> {
+    type Error = i2c::ErrorKind;
+}
+
+impl i2c::I2c for Compat<I2c1> {
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        i2c_v0::Read::read(&mut self.0, address, read).map_err(i2c_error_kind)
+    }
+
+    fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        i2c_v0::Write::write(&mut self.0, address, write).map_err(i2c_error_kind)
+    }
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        i2c_v0::WriteRead::write_read(&mut self.0, address, write, read).map_err(i2c_error_kind)
+    }
+
+    /// Adjacent operations of the same direction form one transfer, a direction change
+    /// a repeated start, with a single stop after the last operation
+    ///
+    /// Transfers of 1 to 255 bytes only, as in the HAL, others fail with
+    /// [`i2c::ErrorKind::Other`] before anything is put on the bus.
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut start = 0;
+        while let Some((end, len)) = transfer(operations, start) {
+            if !(1..256).contains(&len) {
+                return Err(i2c::ErrorKind::Other);
+            }
+            start = end;
+        }
+
+        let mut start = 0;
+        while let Some((end, len)) = transfer(operations, start) {
+            let last = end == operations.len();
+            let stop = if last {
+                hal::i2c::Stop::Automatic
+            } else {
+                hal::i2c::Stop::Software
+            };
+            // A start while the previous transfer is held without a stop is a
+            // repeated start
+            match operations[start] {
+                i2c::Operation::Write(_) => self.0.master_write(address, len, stop),
+                i2c::Operation::Read(_) => self.0.master_read(address, len, stop),
+            }
+
+            let i2c = self.0.inner();
+            for operation in &mut operations[start..end] {
+                match operation {
+                    i2c::Operation::Write(write) => {
+                        for byte in write.iter() {
+                            wait_i2c(i2c, |isr| isr.txis().is_empty())?;
+                            i2c.txdr.write(|w| w.txdata().bits(*byte));
+                        }
+                    }
+                    i2c::Operation::Read(read) => {
+                        for byte in read.iter_mut() {
+                            wait_i2c(i2c, |isr| isr.rxne().is_not_empty())?;
+                            *byte = i2c.rxdr.read().rxdata().bits();
+                        }
+                    }
+                }
+            }
+
+            if last {
+                wait_i2c(i2c, |isr| isr.busy().is_not_busy())?;
+            } else {
+                wait_i2c(i2c, |isr| isr.tc().is_complete())?;
+            }
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+/// End and total length of the adjacent operations of the same direction from `start`
+fn transfer(operations: &[i2c::Operation<'_>], start: usize) -> Option<(usize, usize)> {
+    let is_read = |operation: &i2c::Operation<'_>| matches!(operation, i2c::Operation::Read(_));
+    let first = operations.get(start)?;
+    let end = operations[start..]
+        .iter()
+        .position(|operation| is_read(operation) != is_read(first))
+        .map_or(operations.len(), |offset| start + offset);
+    let len = operations[start..end]
+        .iter()
+        .map(|operation| match operation {
+            i2c::Operation::Write(write) => write.len(),
+            i2c::Operation::Read(read) => read.len(),
+        })
+        .sum();
+    Some((end, len))
+}
+
+/// Waits for `ready` on the I2C status, failing on bus errors as the HAL does
+fn wait_i2c(
+    i2c: &hal::pac::I2C1,
+    ready: impl Fn(&hal::pac::i2c1::isr::R) -> bool,
+) -> Result<(), i2c::ErrorKind> {
+    loop {
+        let isr = i2c.isr.read();
+        if ready(&isr) {
+            return Ok(());
+        } else if isr.berr().is_error() {
+            i2c.icr.write(|w| w.berrcf().set_bit());
+            return Err(i2c_error_kind(hal::i2c::Error::Bus));
+        } else if isr.arlo().is_lost() {
+            i2c.icr.write(|w| w.arlocf().set_bit());
+            return Err(i2c_error_kind(hal::i2c::Error::Arbitration));
+        } else if isr.nackf().bit_is_set() {
+            // The peripheral stops the bus on a NACK, flush what is left to send
+            i2c.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+            if isr.txis().bit_is_set() {
+                i2c.txdr.write(|w| w.txdata().bits(0));
+            }
+            if isr.txe().is_not_empty() {
+                i2c.isr.write(|w| w.txe().set_bit());
+            }
+            return Err(i2c_error_kind(hal::i2c::Error::NotAcknowledge));
+        }
+    }
+}
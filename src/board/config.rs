@@ -0,0 +1,162 @@
+//! config
+//!
+//! Board configuration, built from [`Board::builder`]
+//!
+
+use crate::board::{Board, HertzU32, CORE_FREQUENCY};
+use crate::hal::rcc::PllConfigStrategy;
+use crate::sys::{cache::RamRegion, ClockProfile, ClockSource, VoltageScale};
+use fugit::MillisDurationU32;
+
+#[derive(Clone, Copy)]
+pub struct BoardConfig {
+    pub(crate) vos: VoltageScale,
+    pub(crate) source: ClockSource,
+    pub(crate) sys_ck: HertzU32,
+    pub(crate) hclk: HertzU32,
+    pub(crate) pclk: Option<HertzU32>,
+    pub(crate) pll1_strategy: PllConfigStrategy,
+    pub(crate) i2c1_frequency: HertzU32,
+    pub(crate) usb: bool,
+    pub(crate) hse_css: bool,
+    pub(crate) caches: bool,
+    pub(crate) sdram: bool,
+    pub(crate) qspi_flash: bool,
+    pub(crate) watchdog: Option<MillisDurationU32>,
+    /// Bit set of [`RamRegion::ALL`] indexes
+    non_cacheable: u8,
+}
+
+impl BoardConfig {
+    /// Configuration used by [`Board::take`]
+    pub const fn new() -> Self {
+        Self {
+            vos: VoltageScale::Vos0,
+            source: ClockSource::Hse,
+            sys_ck: CORE_FREQUENCY,
+            hclk: HertzU32::from_raw(240_000_000),
+            pclk: None,
+            pll1_strategy: PllConfigStrategy::Iterative,
+            i2c1_frequency: HertzU32::from_raw(400_000),
+            usb: true,
+            hse_css: false,
+            caches: true,
+            sdram: true,
+            qspi_flash: true,
+            watchdog: None,
+            non_cacheable: 0,
+        }
+    }
+
+    /// Sets the clocks, voltage scale and clock source from a predefined profile
+    pub const fn clock_profile(mut self, profile: ClockProfile) -> Self {
+        self.vos = profile.vos();
+        self.source = profile.source();
+        self.sys_ck = profile.sys_ck();
+        self.hclk = profile.hclk();
+        self.pclk = Some(profile.pclk());
+        self
+    }
+
+    pub const fn vos(mut self, vos: VoltageScale) -> Self {
+        self.vos = vos;
+        self
+    }
+
+    /// Clocks the core from HSI directly, or from HSE through PLL1
+    pub const fn source(mut self, source: ClockSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub const fn sys_ck(mut self, sys_ck: HertzU32) -> Self {
+        self.sys_ck = sys_ck;
+        self
+    }
+
+    pub const fn hclk(mut self, hclk: HertzU32) -> Self {
+        self.hclk = hclk;
+        self
+    }
+
+    /// Clock of every APB bus, chosen by the HAL if not set
+    pub const fn pclk(mut self, pclk: HertzU32) -> Self {
+        self.pclk = Some(pclk);
+        self
+    }
+
+    pub const fn pll1_strategy(mut self, strategy: PllConfigStrategy) -> Self {
+        self.pll1_strategy = strategy;
+        self
+    }
+
+    pub const fn i2c1_frequency(mut self, frequency: HertzU32) -> Self {
+        self.i2c1_frequency = frequency;
+        self
+    }
+
+    /// Brings up USB HS through the ULPI PHY, otherwise the PHY is held in reset
+    pub const fn usb(mut self, enable: bool) -> Self {
+        self.usb = enable;
+        self
+    }
+
+    /// Enables the HSE clock security system, falling back to HSI if the oscillator
+    /// fails, see [`crate::sys::css`]
+    ///
+    /// The application must define a `NonMaskableInt` exception handler calling
+    /// [`crate::sys::css::on_nmi`], otherwise an oscillator failure hangs in the
+    /// default handler. PLL2 and PLL3 are stopped by the fallback.
+    pub const fn hse_css(mut self, enable: bool) -> Self {
+        self.hse_css = enable;
+        self
+    }
+
+    /// Enables the instruction and data caches
+    pub const fn caches(mut self, enable: bool) -> Self {
+        self.caches = enable;
+        self
+    }
+
+    /// Initializes the SDRAM, required by the `sdram-heap` feature
+    pub const fn sdram(mut self, enable: bool) -> Self {
+        self.sdram = enable;
+        self
+    }
+
+    /// Brings up the QUADSPI and the flash on it, see [`crate::drivers::qspi_flash`]
+    pub const fn qspi_flash(mut self, enable: bool) -> Self {
+        self.qspi_flash = enable;
+        self
+    }
+
+    /// Starts the watchdog at setup, resetting the system if not fed within `timeout`,
+    /// see [`crate::board::Watchdog`]
+    pub const fn watchdog(mut self, timeout: MillisDurationU32) -> Self {
+        self.watchdog = Some(timeout);
+        self
+    }
+
+    /// Maps a RAM region as non-cacheable, for the DMA buffers placed in its section
+    pub const fn non_cacheable(mut self, region: RamRegion) -> Self {
+        self.non_cacheable |= 1 << region as u8;
+        self
+    }
+
+    pub(crate) fn non_cacheable_regions(&self) -> impl Iterator<Item = RamRegion> + '_ {
+        RamRegion::ALL
+            .into_iter()
+            .filter(|region| self.non_cacheable & (1 << *region as u8) != 0)
+    }
+
+    /// Brings up the board with this configuration, can only be done once
+    pub fn take(self) -> Board {
+        Board::take_with(self)
+    }
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,260 @@
+//! i2c
+//!
+//! Interrupt driven I2C1 master implementing `embedded-hal-async`
+//!
+//! The HAL configures pins, kernel clock and timings, transfers are then driven
+//! from the I2C1 event and error interrupts. The application binds both
+//! interrupts (`I2C1_EV` and `I2C1_ER`) and forwards them to [`on_interrupt`].
+//!
+
+use crate::board::setup::I2c1;
+use crate::hal::pac;
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal_async::i2c::{
+    ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress,
+};
+
+/// Maximum number of bytes per NBYTES reload
+const MAX_CHUNK: usize = 255;
+
+static WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+type Isr = pac::i2c1::isr::R;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Write,
+    Read,
+}
+
+impl Direction {
+    fn of(operation: &Operation<'_>) -> Self {
+        match operation {
+            Operation::Write(_) => Direction::Write,
+            Operation::Read(_) => Direction::Read,
+        }
+    }
+}
+
+fn len_of(operation: &Operation<'_>) -> usize {
+    match operation {
+        Operation::Write(bytes) => bytes.len(),
+        Operation::Read(buffer) => buffer.len(),
+    }
+}
+
+/// I2C1 event and error interrupt handler
+///
+/// Masks the interrupt sources and wakes the pending transfer, which unmasks
+/// them again if it has to keep waiting.
+pub fn on_interrupt() {
+    let regs = unsafe { &*pac::I2C1::ptr() };
+    regs.cr1.modify(|_, w| {
+        w.txie()
+            .clear_bit()
+            .rxie()
+            .clear_bit()
+            .nackie()
+            .clear_bit()
+            .stopie()
+            .clear_bit()
+            .tcie()
+            .clear_bit()
+            .errie()
+            .clear_bit()
+    });
+    interrupt::free(|cs| {
+        if let Some(waker) = WAKER.borrow(cs).borrow_mut().take() {
+            waker.wake();
+        }
+    });
+}
+
+pub struct AsyncI2c1 {
+    i2c: I2c1,
+}
+
+impl AsyncI2c1 {
+    pub fn new(i2c: I2c1) -> Self {
+        Self { i2c }
+    }
+
+    pub fn free(self) -> I2c1 {
+        self.i2c
+    }
+
+    fn regs(&self) -> &pac::i2c1::RegisterBlock {
+        unsafe { &*pac::I2C1::ptr() }
+    }
+
+    /// Waits until `ready` holds for the status register, failing on bus errors
+    async fn wait(&self, ready: fn(&Isr) -> bool) -> Result<(), ErrorKind> {
+        let regs = self.regs();
+        poll_fn(|cx| {
+            let isr = regs.isr.read();
+            if let Some(error) = Self::error(regs, &isr) {
+                return Poll::Ready(Err(error));
+            }
+            if ready(&isr) {
+                return Poll::Ready(Ok(()));
+            }
+            interrupt::free(|cs| *WAKER.borrow(cs).borrow_mut() = Some(cx.waker().clone()));
+            // A flag raised after the status read pends the interrupt as soon as it is unmasked
+            regs.cr1.modify(|_, w| {
+                w.txie()
+                    .set_bit()
+                    .rxie()
+                    .set_bit()
+                    .nackie()
+                    .set_bit()
+                    .stopie()
+                    .set_bit()
+                    .tcie()
+                    .set_bit()
+                    .errie()
+                    .set_bit()
+            });
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn error(regs: &pac::i2c1::RegisterBlock, isr: &Isr) -> Option<ErrorKind> {
+        let error = if isr.berr().bit_is_set() {
+            regs.icr.write(|w| w.berrcf().set_bit());
+            ErrorKind::Bus
+        } else if isr.arlo().bit_is_set() {
+            regs.icr.write(|w| w.arlocf().set_bit());
+            ErrorKind::ArbitrationLoss
+        } else if isr.ovr().bit_is_set() {
+            regs.icr.write(|w| w.ovrcf().set_bit());
+            ErrorKind::Overrun
+        } else if isr.nackf().bit_is_set() {
+            // A STOP condition is generated by hardware, flush any pending data
+            regs.icr.write(|w| w.nackcf().set_bit());
+            regs.isr.write(|w| w.txe().set_bit());
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+        } else {
+            return None;
+        };
+        Some(error)
+    }
+
+    /// Next non-empty operation direction after `index`
+    fn next_direction(operations: &[Operation<'_>], index: usize) -> Option<Direction> {
+        operations[index + 1..]
+            .iter()
+            .find(|operation| len_of(operation) > 0)
+            .map(Direction::of)
+    }
+
+    async fn stop(&self) -> Result<(), ErrorKind> {
+        let regs = self.regs();
+        regs.cr2.modify(|_, w| w.stop().set_bit());
+        self.wait(|isr| isr.stopf().bit_is_set()).await?;
+        regs.icr.write(|w| w.stopcf().set_bit());
+        Ok(())
+    }
+}
+
+impl ErrorType for AsyncI2c1 {
+    type Error = ErrorKind;
+}
+
+impl I2c<SevenBitAddress> for AsyncI2c1 {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let regs = self.regs();
+        // Discard flags left by an aborted transfer
+        regs.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+
+        // Address only transfer
+        if operations.iter().all(|operation| len_of(operation) == 0) {
+            regs.cr2.write(|w| {
+                w.sadd()
+                    .bits(u16::from(address) << 1)
+                    .rd_wrn()
+                    .clear_bit()
+                    .nbytes()
+                    .bits(0)
+                    .autoend()
+                    .clear_bit()
+                    .start()
+                    .set_bit()
+            });
+            self.wait(|isr| isr.tc().bit_is_set()).await?;
+            return self.stop().await;
+        }
+
+        // Adjacent operations of the same direction are merged with NBYTES reloads,
+        // a direction change issues a repeated START
+        let mut previous: Option<Direction> = None;
+        for index in 0..operations.len() {
+            let len = len_of(&operations[index]);
+            if len == 0 {
+                continue;
+            }
+            let direction = Direction::of(&operations[index]);
+            let merge_next = Self::next_direction(operations, index) == Some(direction);
+
+            let mut offset = 0;
+            while offset < len {
+                let chunk = (len - offset).min(MAX_CHUNK);
+                let reload = offset + chunk < len || merge_next;
+
+                if previous == Some(direction) {
+                    self.wait(|isr| isr.tcr().bit_is_set()).await?;
+                    regs.cr2
+                        .modify(|_, w| w.nbytes().bits(chunk as u8).reload().bit(reload));
+                } else {
+                    if previous.is_some() {
+                        self.wait(|isr| isr.tc().bit_is_set()).await?;
+                    }
+                    regs.cr2.write(|w| {
+                        w.sadd()
+                            .bits(u16::from(address) << 1)
+                            .rd_wrn()
+                            .bit(direction == Direction::Read)
+                            .nbytes()
+                            .bits(chunk as u8)
+                            .reload()
+                            .bit(reload)
+                            .autoend()
+                            .clear_bit()
+                            .start()
+                            .set_bit()
+                    });
+                }
+
+                match &mut operations[index] {
+                    Operation::Write(bytes) => {
+                        for byte in &bytes[offset..offset + chunk] {
+                            self.wait(|isr| isr.txis().bit_is_set()).await?;
+                            regs.txdr.write(|w| w.txdata().bits(*byte));
+                        }
+                    }
+                    Operation::Read(buffer) => {
+                        for byte in &mut buffer[offset..offset + chunk] {
+                            self.wait(|isr| isr.rxne().bit_is_set()).await?;
+                            *byte = regs.rxdr.read().rxdata().bits();
+                        }
+                    }
+                }
+
+                previous = Some(direction);
+                offset += chunk;
+            }
+        }
+
+        self.wait(|isr| isr.tc().bit_is_set()).await?;
+        self.stop().await
+    }
+}
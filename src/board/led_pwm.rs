@@ -0,0 +1,55 @@
+//! led_pwm
+//!
+//! TIM7 ticking the software PWM of the user LEDs, stopped until the application
+//! enables it, so the interrupt does not run while no LED is dimmed
+//!
+
+use crate::board::LED_PWM_TICK_FREQUENCY;
+use crate::hal::{
+    pac,
+    rcc::{rec, CoreClocks},
+    timer::{Event, Timer},
+};
+
+pub struct LedPwmTimer {
+    timer: Timer<pac::TIM7>,
+    enabled: bool,
+}
+
+impl LedPwmTimer {
+    /// Sets TIM7 up at [`LED_PWM_TICK_FREQUENCY`], stopped
+    pub(crate) fn new(tim7: pac::TIM7, prec: rec::Tim7, clocks: &CoreClocks) -> Self {
+        let mut timer = Timer::tim7(tim7, prec, clocks);
+        timer.pause();
+        // Load the period without raising an update interrupt
+        timer.urs_counter_only();
+        timer.set_freq(LED_PWM_TICK_FREQUENCY);
+        timer.apply_freq();
+        timer.clear_irq();
+        Self {
+            timer,
+            enabled: false,
+        }
+    }
+
+    /// Starts the timer and its update interrupt, the TIM7 interrupt then calling the
+    /// `tick` of the PWM driven LEDs
+    ///
+    /// Does nothing once enabled.
+    pub fn enable(&mut self) {
+        if !self.enabled {
+            self.timer.listen(Event::TimeOut);
+            self.timer.resume();
+            self.enabled = true;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Clears the update interrupt, from the TIM7 interrupt handler
+    pub fn clear_irq(&mut self) {
+        self.timer.clear_irq();
+    }
+}
@@ -1,8 +1,38 @@
 #[cfg(feature = "async")]
 pub mod async_impl;
+#[cfg(feature = "async")]
+mod i2c;
 
 #[cfg(not(feature = "async"))]
 pub mod non_async_impl;
 
+// Board of the variant selected by the `async` feature
+#[cfg(feature = "async")]
+pub use async_impl::*;
+#[cfg(not(feature = "async"))]
+pub use non_async_impl::*;
+
+mod backup;
+mod clocks;
+pub mod compat;
+mod config;
+mod led_pwm;
+mod qspi;
+pub mod sdram;
+mod setup;
+mod watchdog;
+
+pub use crate::sys::{cache::RamRegion, ClockProfile, VoltageScale};
+pub use backup::{Backup, Persistent, BACKUP_CAPACITY};
+pub use config::BoardConfig;
+pub use led_pwm::LedPwmTimer;
+pub use qspi::{MappedQspiFlash, QspiError, QspiStatic, QuadSpi, QSPI_BASE, QSPI_SIZE};
+pub use sdram::Sdram;
+pub use watchdog::{CheckIn, ClientId, Watchdog, WatchdogError, MAX_CLIENTS};
+
 pub use fugit::HertzU32;
+/// Default system clock, the clocks the board actually runs with are `Board::clocks`
 pub const CORE_FREQUENCY: HertzU32 = HertzU32::from_raw(480_000_000);
+/// Tick rate of the LED PWM timer, giving a 100 Hz software PWM
+pub const LED_PWM_TICK_FREQUENCY: HertzU32 =
+    HertzU32::from_raw(100 * crate::drivers::led::PWM_STEPS as u32);
@@ -1,130 +1,106 @@
 //! board
 
-use crate::board::CORE_FREQUENCY;
-use crate::{
-    drivers::{led, pmic},
-    hal, sys,
+use crate::board::{
+    clocks,
+    compat::Compat,
+    setup::{self, Resources},
+    Backup, BoardConfig, LedPwmTimer, Sdram, Watchdog,
 };
-use core::sync::atomic::{AtomicBool, Ordering};
-use defmt::debug;
-use hal::{
-    gpio::{Output, Pin, PinState, PushPull},
-    pac,
-    prelude::*,
-    rcc,
-    usb_hs::{UsbBus, USB1_ULPI},
+use crate::drivers::pmic;
+use crate::sys::{ClockChangeListener, Clocks, ResetReason};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
 };
+use critical_section::Mutex;
+use embedded_hal_bus::i2c::CriticalSectionDevice;
+use static_cell::StaticCell;
 
-type DigitalOutputPin<const P: char, const N: u8> = Pin<P, N, Output<PushPull>>;
-pub type LedRed = led::Led<DigitalOutputPin<'K', 5>>;
-pub type LedGreen = led::Led<DigitalOutputPin<'K', 6>>;
-pub type LedBlue = led::Led<DigitalOutputPin<'K', 7>>;
-pub type UsbPer = USB1_ULPI;
-pub type UsbBusImpl = UsbBus<UsbPer>;
+pub use setup::{I2c1, LedBlue, LedGreen, LedRed, QspiFlashImpl, RgbLedImpl, UsbBusImpl, UsbPer};
+/// I2C1 bus, shared by creating [`I2c1Device`]s on it
+pub type I2c1Bus = Mutex<RefCell<Compat<I2c1>>>;
+pub type I2c1Device = CriticalSectionDevice<'static, Compat<I2c1>>;
+pub type PmicImpl = pmic::Pmic<I2c1Device>;
 
 pub struct Board {
     pub led_red: LedRed,
     pub led_green: LedGreen,
     pub led_blue: LedBlue,
-    pub usb: UsbPer,
+    pub led_pwm_timer: LedPwmTimer,
+    pub usb: Option<UsbPer>,
+    pub pmic: PmicImpl,
+    pub i2c1: &'static I2c1Bus,
+    pub backup: Backup,
+    /// Cause of the reset before this boot
+    pub reset_reason: ResetReason,
+    pub watchdog: Watchdog,
+    pub sdram: Option<Sdram>,
+    pub qspi_flash: Option<QspiFlashImpl>,
+    /// Clocks frozen at setup, e.g. to start a SysTick monotonic
+    pub clocks: Clocks,
 }
 
 impl Board {
+    /// Brings up the board with the default configuration
     pub fn take() -> Self {
-        static TAKEN: AtomicBool = AtomicBool::new(false);
-        debug_assert!(!TAKEN.swap(true, Ordering::SeqCst));
-        Self::setup()
+        Self::builder().take()
     }
 
-    fn setup() -> Self {
-        // Reset previous configuration and enable external oscillator as HSE source (25 MHz)
-        sys::Clk::new().reset().enable_ext_clock();
-        let dp = pac::Peripherals::take().unwrap();
-
-        // Configure power domains and clock tree
-        let pwrcfg = dp.PWR.constrain().vos0(&dp.SYSCFG).freeze();
-        let ccdr = dp
-            .RCC
-            .constrain()
-            .use_hse(25.MHz())
-            .bypass_hse()
-            .sys_ck(CORE_FREQUENCY)
-            .hclk(240.MHz())
-            .pll1_strategy(rcc::PllConfigStrategy::Iterative)
-            .freeze(pwrcfg, &dp.SYSCFG);
-
-        debug_assert_eq!(sys::Clk::get_source(), Some(sys::ClkSource::Pll1));
-        debug_assert_eq!(sys::Clk::get_pll_source(), sys::PllSourceVariant::Hse);
-
-        // GPIOs
-        let (gpioa, gpiob, gpioc, gpioh, gpioi, gpioj) = {
-            (
-                dp.GPIOA.split(ccdr.peripheral.GPIOA),
-                dp.GPIOB.split(ccdr.peripheral.GPIOB),
-                dp.GPIOC.split(ccdr.peripheral.GPIOC),
-                dp.GPIOH.split_without_reset(ccdr.peripheral.GPIOH), // Do not do a reset since external oscillator is enabled by GPIOH1
-                dp.GPIOI.split(ccdr.peripheral.GPIOI),
-                dp.GPIOJ.split(ccdr.peripheral.GPIOJ),
-            )
-        };
-        // Enable ULPI transceiver (GPIOJ4)
-        let mut ulpi_reset = gpioj.pj4.into_push_pull_output();
-        ulpi_reset.set_high();
-
-        let usb = USB1_ULPI::new(
-            dp.OTG1_HS_GLOBAL,
-            dp.OTG1_HS_DEVICE,
-            dp.OTG1_HS_PWRCLK,
-            gpioa.pa5.into_alternate(),
-            gpioi.pi11.into_alternate(),
-            gpioh.ph4.into_alternate(),
-            gpioc.pc0.into_alternate(),
-            gpioa.pa3.into_alternate(),
-            gpiob.pb0.into_alternate(),
-            gpiob.pb1.into_alternate(),
-            gpiob.pb10.into_alternate(),
-            gpiob.pb11.into_alternate(),
-            gpiob.pb12.into_alternate(),
-            gpiob.pb13.into_alternate(),
-            gpiob.pb5.into_alternate(),
-            ccdr.peripheral.USB1OTG,
-            &ccdr.clocks,
-        );
+    /// Starts a configuration to bring up the board with, see [`BoardConfig::take`]
+    pub fn builder() -> BoardConfig {
+        BoardConfig::new()
+    }
 
-        // User LEDs
-        let gpiok = dp.GPIOK.split(ccdr.peripheral.GPIOK);
-        let (output_k5, output_k6, output_k7) = (
-            gpiok.pk5.into_push_pull_output_in_state(PinState::High),
-            gpiok.pk6.into_push_pull_output_in_state(PinState::High),
-            gpiok.pk7.into_push_pull_output_in_state(PinState::High),
-        );
+    pub(crate) fn take_with(config: BoardConfig) -> Self {
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+        debug_assert!(!TAKEN.swap(true, Ordering::SeqCst));
+        Self::setup(&config)
+    }
 
-        let led_red = led::Led::new(output_k5);
-        let led_green = led::Led::new(output_k6);
-        let led_blue = led::Led::new(output_k7);
+    fn setup(config: &BoardConfig) -> Self {
+        let Resources {
+            led_red,
+            led_green,
+            led_blue,
+            led_pwm_timer,
+            usb,
+            i2c1,
+            backup,
+            reset_reason,
+            watchdog,
+            sdram,
+            qspi_flash,
+            clocks,
+        } = setup::setup(config);
 
-        // PMIC
-        let (i2c1_scl, i2c1_sda) = (
-            gpiob.pb6.into_alternate_open_drain(),
-            gpiob.pb7.into_alternate_open_drain(),
-        );
-        let i2c1 = dp.I2C1.i2c(
-            (i2c1_scl, i2c1_sda),
-            400.kHz(),
-            ccdr.peripheral.I2C1,
-            &ccdr.clocks,
-        );
-        let mut pmic = pmic::Pmic::new(i2c1);
-        match pmic.device_id() {
-            Ok(id) => debug!("PMIC device ID: {:X}", id),
-            Err(_) => debug!("PMIC device ID read error"),
-        }
+        // I2C1 bus, shared between the PMIC and the application
+        static I2C1_BUS: StaticCell<I2c1Bus> = StaticCell::new();
+        let i2c1: &'static I2c1Bus = I2C1_BUS.init(Mutex::new(RefCell::new(Compat::new(i2c1))));
 
         Board {
             led_red,
             led_green,
             led_blue,
+            led_pwm_timer,
             usb,
+            pmic: pmic::Pmic::new(CriticalSectionDevice::new(i2c1)),
+            i2c1,
+            backup,
+            reset_reason,
+            watchdog,
+            sdram,
+            qspi_flash,
+            clocks,
         }
     }
 }
+
+impl ClockChangeListener for &I2c1Bus {
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        // Hold the bus so no transfer is in progress
+        critical_section::with(|cs| {
+            let _i2c1 = self.borrow_ref_mut(cs);
+            clocks::retime_i2c1(clocks);
+        });
+    }
+}
@@ -0,0 +1,352 @@
+//! qspi
+//!
+//! QUADSPI bank 1 in indirect mode, the [`Backend`] of the on-board MX25L12833F flash
+//! Memory-mapped mode reads the flash at 0x90000000, see [`MappedQspiFlash`], and the
+//! statics of [`qspi_static!`]
+//!
+//! [`qspi_static!`]: crate::qspi_static
+//!
+
+use crate::drivers::qspi_flash::{
+    Backend, Command, Instruction, Lines, QspiFlash, CAPACITY, QUAD_IO_READ_DUMMY_CYCLES,
+};
+use crate::sys::{
+    cache::{self, Attributes, MpuRegion},
+    ClockChangeListener, Clocks,
+};
+use cortex_m::peripheral::SCB;
+use fugit::HertzU32;
+use stm32h7xx_hal::pac;
+
+pub const QSPI_BASE: usize = 0x9000_0000;
+pub const QSPI_SIZE: usize = CAPACITY as usize;
+
+/// MPU region of the flash out of memory-mapped mode, faulting on any access
+/// including speculative reads
+pub(crate) const UNMAPPED_REGION: MpuRegion = MpuRegion::new(
+    QSPI_BASE as u32,
+    QSPI_SIZE as u32,
+    Attributes::StronglyOrdered,
+)
+.no_access();
+/// MPU region of the mapped flash, read-only, cached and executable in place
+const MAPPED_REGION: MpuRegion =
+    MpuRegion::new(QSPI_BASE as u32, QSPI_SIZE as u32, Attributes::WriteThrough)
+        .read_only()
+        .executable();
+
+/// Fastest flash clock for quad reads with the default dummy cycles
+const MAX_CLOCK: HertzU32 = HertzU32::from_raw(84_000_000);
+/// Chip select high time between commands, the longest of the flash
+const CS_HIGH_NS: u32 = 30;
+
+/// Status polls before giving up on a command, milliseconds at any AHB clock
+const TIMEOUT_POLLS: u32 = 100_000;
+
+/// CR ABORT
+const CR_ABORT: u32 = 1 << 1;
+
+// SR flags
+const SR_TCF: u32 = 1 << 1;
+const SR_FTF: u32 = 1 << 2;
+const SR_BUSY: u32 = 1 << 5;
+
+// CCR FMODE
+const FMODE_INDIRECT_WRITE: u32 = 0b00;
+const FMODE_INDIRECT_READ: u32 = 0b01;
+const FMODE_MEMORY_MAPPED: u32 = 0b11;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum QspiError {
+    /// QUADSPI did not complete the command, then aborted, e.g. without a flash
+    /// answering
+    Timeout,
+}
+
+/// QUADSPI talking to the flash, its clock fixed at setup or by a clock change
+pub struct QuadSpi {
+    clock: HertzU32,
+}
+
+impl QuadSpi {
+    /// Configures the QUADSPI, its clock and pins being enabled
+    ///
+    /// `hclk` is the kernel clock, divided down to at most 84 MHz
+    pub(crate) fn init(hclk: HertzU32) -> Self {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        // FSIZE, 2^(FSIZE + 1) bytes
+        let fsize = CAPACITY.ilog2() - 1;
+        unsafe {
+            qspi.dcr.write(|w| w.bits(fsize << 16));
+        }
+        let mut quad_spi = Self { clock: hclk };
+        // Idle out of reset
+        quad_spi.configure(hclk);
+        quad_spi
+    }
+
+    pub fn clock(&self) -> HertzU32 {
+        self.clock
+    }
+
+    /// Sets the prescaler and chip select high time for the `hclk` kernel clock, once
+    /// the QUADSPI is idle
+    fn set_clock(&mut self, hclk: HertzU32) -> Result<(), QspiError> {
+        self.wait_idle()?;
+        self.configure(hclk);
+        Ok(())
+    }
+
+    fn configure(&mut self, hclk: HertzU32) {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        let prescaler = hclk.raw().div_ceil(MAX_CLOCK.raw()).clamp(1, 256);
+        self.clock = hclk / prescaler;
+        let cs_high = (CS_HIGH_NS as u64 * self.clock.raw() as u64)
+            .div_ceil(1_000_000_000)
+            .clamp(1, 8) as u32;
+
+        unsafe {
+            qspi.cr.write(|w| w.bits(0));
+            qspi.dcr
+                .modify(|r, w| w.bits(r.bits() & !(0b111 << 8) | (cs_high - 1) << 8));
+            // Prescaler, sampling shifted by half a cycle, enabled
+            qspi.cr
+                .write(|w| w.bits((prescaler - 1) << 24 | 1 << 4 | 1 << 0));
+        }
+    }
+
+    /// CCR value of `command` in `fmode`, the instruction on a single line
+    fn ccr(fmode: u32, command: &Command, data: bool) -> u32 {
+        let lines = |lines: Lines| match lines {
+            Lines::Single => 0b01,
+            Lines::Quad => 0b11,
+        };
+        let (admode, adsize) = match command.address {
+            Some(_) => (lines(command.address_lines), 0b10),
+            None => (0b00, 0b00),
+        };
+        let dmode = if data {
+            lines(command.data_lines)
+        } else {
+            0b00
+        };
+        fmode << 26
+            | dmode << 24
+            | (command.dummy_cycles as u32 & 0x1F) << 18
+            | adsize << 12
+            | admode << 10
+            | 0b01 << 8
+            | command.instruction as u32
+    }
+
+    /// Starts `command`, moving `len` bytes of data in `fmode`
+    fn start(&mut self, fmode: u32, command: Command, len: usize) -> Result<(), QspiError> {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        self.wait_idle()?;
+        unsafe {
+            // Clear the transfer complete flag
+            qspi.fcr.write(|w| w.bits(SR_TCF));
+            if len > 0 {
+                qspi.dlr.write(|w| w.bits(len as u32 - 1));
+            }
+            // Started here without an address
+            qspi.ccr
+                .write(|w| w.bits(Self::ccr(fmode, &command, len > 0)));
+            if let Some(address) = command.address {
+                qspi.ar.write(|w| w.bits(address));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), QspiError> {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        self.wait(|sr| sr & SR_TCF != 0)?;
+        unsafe { qspi.fcr.write(|w| w.bits(SR_TCF)) };
+        Ok(())
+    }
+
+    /// Polls SR until `ready`, aborting the command on timeout
+    fn wait(&mut self, ready: impl Fn(u32) -> bool) -> Result<(), QspiError> {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        if (0..TIMEOUT_POLLS).any(|_| ready(qspi.sr.read().bits())) {
+            return Ok(());
+        }
+        // Leave the QUADSPI idle for the next command
+        self.abort()?;
+        Err(QspiError::Timeout)
+    }
+
+    fn wait_idle(&mut self) -> Result<(), QspiError> {
+        self.wait(|sr| sr & SR_BUSY == 0)
+    }
+
+    /// Reads the flash at [`QSPI_BASE`] with quad reads from now on
+    fn memory_map(&mut self) -> Result<(), QspiError> {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        let read = Command::new(Instruction::QuadIoRead)
+            .address(0, Lines::Quad)
+            .dummy_cycles(QUAD_IO_READ_DUMMY_CYCLES)
+            .data_lines(Lines::Quad);
+        self.wait_idle()?;
+        unsafe {
+            qspi.ccr
+                .write(|w| w.bits(Self::ccr(FMODE_MEMORY_MAPPED, &read, true)));
+        }
+        Ok(())
+    }
+
+    /// Stops the command in progress, or leaves memory-mapped mode, back to indirect
+    /// commands
+    fn abort(&mut self) -> Result<(), QspiError> {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        unsafe { qspi.cr.modify(|r, w| w.bits(r.bits() | CR_ABORT)) };
+        let aborted = (0..TIMEOUT_POLLS)
+            .any(|_| qspi.cr.read().bits() & CR_ABORT == 0 && qspi.sr.read().bits() & SR_BUSY == 0);
+        if !aborted {
+            return Err(QspiError::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Data register, accessed by byte to move one byte of the FIFO
+    fn dr() -> *mut u8 {
+        let qspi = unsafe { &(*pac::QUADSPI::ptr()) };
+        core::ptr::addr_of!(qspi.dr) as *mut u8
+    }
+}
+
+impl Backend for QuadSpi {
+    type Error = QspiError;
+
+    fn read(&mut self, command: Command, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.start(FMODE_INDIRECT_READ, command, buffer.len())?;
+        for byte in buffer.iter_mut() {
+            // FIFO holding data, or the transfer complete with the last bytes
+            self.wait(|sr| sr & (SR_FTF | SR_TCF) != 0)?;
+            *byte = unsafe { Self::dr().read_volatile() };
+        }
+        self.finish()
+    }
+
+    fn write(&mut self, command: Command, data: &[u8]) -> Result<(), Self::Error> {
+        self.start(FMODE_INDIRECT_WRITE, command, data.len())?;
+        for byte in data {
+            self.wait(|sr| sr & SR_FTF != 0)?;
+            unsafe { Self::dr().write_volatile(*byte) };
+        }
+        self.finish()
+    }
+}
+
+impl ClockChangeListener for QspiFlash<QuadSpi> {
+    /// Must not be called during a command
+    ///
+    /// Panics, within [`switch_profile`](crate::sys::switch_profile), if the QUADSPI
+    /// does not go idle before the new clock is set.
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        self.backend_mut()
+            .set_clock(clocks.hclk)
+            .expect("QUADSPI stuck during a clock change");
+    }
+}
+
+impl QspiFlash<QuadSpi> {
+    /// Maps the flash at [`QSPI_BASE`], read-only, cacheable and executable, until
+    /// [`MappedQspiFlash::into_command_mode`]
+    ///
+    /// The data cache is cleaned and invalidated, and the instruction cache
+    /// invalidated, dropping lines cached before the flash was last written. On
+    /// error the flash is returned in command mode.
+    pub fn into_memory_mapped(mut self) -> Result<MappedQspiFlash, (Self, QspiError)> {
+        if let Err(err) = self.backend_mut().memory_map() {
+            return Err((self, err));
+        }
+        cache::reconfigure_mpu_region(MAPPED_REGION);
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+        if SCB::dcache_enabled() {
+            cp.SCB.clean_invalidate_dcache(&mut cp.CPUID);
+        }
+        cp.SCB.invalidate_icache();
+        Ok(MappedQspiFlash { flash: self })
+    }
+}
+
+/// QSPI flash in memory-mapped mode, its content and the `.qspi` statics readable at
+/// [`QSPI_BASE`] through the cache
+///
+/// Out of this mode the MPU faults on any access to the flash.
+pub struct MappedQspiFlash {
+    flash: QspiFlash<QuadSpi>,
+}
+
+impl MappedQspiFlash {
+    /// The whole flash
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(QSPI_BASE as *const u8, QSPI_SIZE) }
+    }
+
+    /// Leaves memory-mapped mode, for commands to program or erase the flash
+    ///
+    /// On error the flash is returned still mapped, the MPU allowing reads.
+    pub fn into_command_mode(mut self) -> Result<QspiFlash<QuadSpi>, (Self, QspiError)> {
+        if let Err(err) = self.flash.backend_mut().abort() {
+            return Err((self, err));
+        }
+        cache::reconfigure_mpu_region(UNMAPPED_REGION);
+        Ok(self.flash)
+    }
+}
+
+impl ClockChangeListener for MappedQspiFlash {
+    /// Panics, within [`switch_profile`](crate::sys::switch_profile), if the QUADSPI
+    /// does not leave and reenter memory-mapped mode.
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        let quad_spi = self.flash.backend_mut();
+        quad_spi
+            .abort()
+            .and_then(|()| quad_spi.set_clock(clocks.hclk))
+            .and_then(|()| quad_spi.memory_map())
+            .expect("QUADSPI stuck during a clock change");
+    }
+}
+
+/// Static in the `.qspi` section, see [`qspi_static!`](crate::qspi_static)
+pub struct QspiStatic<T> {
+    value: T,
+}
+
+impl<T> QspiStatic<T> {
+    #[doc(hidden)]
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    /// The value in the flash, readable while it is mapped
+    pub fn get<'a>(&'a self, _mapped: &'a MappedQspiFlash) -> &'a T {
+        // Opaque to the compiler, so reads are not folded out of the flash
+        unsafe { &*core::hint::black_box(&self.value as *const T) }
+    }
+}
+
+/// Places a static in the `.qspi` section of the QSPI flash, read through
+/// [`QspiStatic::get`] while the flash is memory-mapped
+///
+/// The section is NOLOAD, its content is extracted from the example linked by the
+/// `qspi-image` alias and programmed into the flash separately.
+///
+/// ```ignore
+/// qspi_static!(FONT: [u8; 4096] = *include_bytes!("font.bin"));
+///
+/// let mapped = qspi_flash.into_memory_mapped().map_err(|(_, err)| err)?;
+/// let glyphs: &[u8; 4096] = FONT.get(&mapped);
+/// ```
+#[macro_export]
+macro_rules! qspi_static {
+    ($(#[$attr:meta])* $vis:vis $name:ident: $ty:ty = $value:expr) => {
+        $(#[$attr])*
+        #[link_section = ".qspi"]
+        $vis static $name: $crate::board::QspiStatic<$ty> =
+            $crate::board::QspiStatic::new($value);
+    };
+}
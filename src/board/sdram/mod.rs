@@ -0,0 +1,165 @@
+//! sdram
+//!
+//! 8M AS4C4M16SA SDRAM on the FMC SDRAM bank 1, at 0xC0000000
+//! The `.sdram` section of memory.x comes first, the rest of the SDRAM backs the heap
+//! with the `sdram-heap` feature
+//! Self-test for manufacturing, see [`self_test()`]
+//!
+
+use crate::sys::{ClockChangeListener, Clocks};
+use core::sync::atomic::{AtomicBool, Ordering};
+use fugit::HertzU32;
+use stm32h7xx_hal::pac;
+
+mod self_test;
+
+pub use self_test::{self_test, Coverage, Failure, SelfTestReport, Test, MAX_FAILURES};
+
+pub const SDRAM_BASE: usize = 0xC000_0000;
+pub const SDRAM_SIZE: usize = 8 * 1024 * 1024;
+
+/// Fastest SDRAM clock of the AS4C4M16SA-7 at CAS latency 3
+const MAX_SDCLK: HertzU32 = HertzU32::from_raw(143_000_000);
+const CAS_LATENCY: u32 = 3;
+
+// AS4C4M16SA-7 timings in ns, or in clock cycles
+const T_RC_NS: u32 = 63;
+const T_RAS_NS: u32 = 42;
+const T_RP_NS: u32 = 21;
+const T_RCD_NS: u32 = 21;
+const T_XSR_NS: u32 = 70;
+const T_WR_CYCLES: u32 = 2;
+const T_MRD_CYCLES: u32 = 2;
+/// 4096 rows refreshed every 64 ms
+const REFRESH_PERIOD_NS: u32 = 64_000_000 / 4096;
+const POWER_UP_DELAY_US: u32 = 200;
+const AUTO_REFRESH_COMMANDS: u32 = 8;
+
+// SDCMR MODE
+const MODE_CLOCK_ENABLE: u32 = 0b001;
+const MODE_PALL: u32 = 0b010;
+const MODE_AUTO_REFRESH: u32 = 0b011;
+const MODE_LOAD_MODE_REGISTER: u32 = 0b100;
+/// SDCMR CTB1, commands target bank 1
+const COMMAND_BANK1: u32 = 1 << 4;
+/// Burst length 1, sequential, CAS latency, single location write
+const MODE_REGISTER: u32 = CAS_LATENCY << 4 | 1 << 9;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "sdram-heap")]
+#[global_allocator]
+static HEAP: embedded_alloc::LlffHeap = embedded_alloc::LlffHeap::empty();
+
+#[cfg(feature = "sdram-heap")]
+extern "C" {
+    /// End of the `.sdram` section
+    static __esdram: u8;
+}
+
+/// Initialized SDRAM, its timings fixed for the clock at setup
+pub struct Sdram {
+    sdclk: HertzU32,
+}
+
+impl Sdram {
+    /// Initializes the SDRAM, the FMC clock and pins being enabled
+    ///
+    /// `hclk` clocks the FMC, the SDRAM runs at half of it
+    pub(crate) fn init(hclk: HertzU32, sys_ck: HertzU32) -> Self {
+        let sdclk = hclk / 2;
+        assert!(sdclk <= MAX_SDCLK, "SDRAM clock too fast");
+        let fmc = unsafe { &(*pac::FMC::ptr()) };
+
+        // 8 column bits, 12 row bits, 16-bit data, 4 banks, SDCLK at HCLK / 2, read
+        // bursts and a 1 cycle read pipe delay
+        let sdcr = 0b01 << 2 | 0b01 << 4 | 1 << 6 | CAS_LATENCY << 7 | 0b10 << 10;
+        unsafe {
+            fmc.sdbank1()
+                .sdcr
+                .write(|w| w.bits(sdcr | 1 << 12 | 0b01 << 13));
+            fmc.sdbank1().sdtr.write(|w| w.bits(Self::timings(sdclk)));
+            fmc.bcr1.modify(|r, w| w.bits(r.bits() | 1 << 31));
+        }
+
+        Self::command(MODE_CLOCK_ENABLE, 0);
+        cortex_m::asm::delay(sys_ck.raw() / 1_000_000 * POWER_UP_DELAY_US);
+        Self::command(MODE_PALL, 0);
+        Self::command(MODE_AUTO_REFRESH, (AUTO_REFRESH_COMMANDS - 1) << 5);
+        Self::command(MODE_LOAD_MODE_REGISTER, MODE_REGISTER << 9);
+        Self::set_refresh_rate(sdclk);
+
+        INITIALIZED.store(true, Ordering::Release);
+        Self { sdclk }
+    }
+
+    pub fn is_initialized() -> bool {
+        INITIALIZED.load(Ordering::Acquire)
+    }
+
+    pub fn sdclk(&self) -> HertzU32 {
+        self.sdclk
+    }
+
+    /// The whole SDRAM, `.sdram` statics and heap included
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        SDRAM_BASE as *mut u8
+    }
+
+    /// Hands the SDRAM past the `.sdram` section to the global allocator
+    #[cfg(feature = "sdram-heap")]
+    pub(crate) fn init_heap(&self) {
+        unsafe {
+            let start = core::ptr::addr_of!(__esdram) as usize;
+            HEAP.init(start, SDRAM_BASE + SDRAM_SIZE - start);
+        }
+    }
+
+    /// SDTR values, ceiled to SDRAM clock cycles
+    fn timings(sdclk: HertzU32) -> u32 {
+        let cycles = |ns: u32| {
+            let cycles = (ns as u64 * sdclk.raw() as u64).div_ceil(1_000_000_000) as u32;
+            cycles.clamp(1, 16)
+        };
+        let (trc, tras, trp, trcd) = (
+            cycles(T_RC_NS),
+            cycles(T_RAS_NS),
+            cycles(T_RP_NS),
+            cycles(T_RCD_NS),
+        );
+        // The write recovery time covers the row timings
+        let twr = T_WR_CYCLES
+            .max(tras.saturating_sub(trcd))
+            .max(trc.saturating_sub(trcd + trp));
+        (T_MRD_CYCLES - 1)
+            | (cycles(T_XSR_NS) - 1) << 4
+            | (tras - 1) << 8
+            | (trc - 1) << 12
+            | (twr - 1) << 16
+            | (trp - 1) << 20
+            | (trcd - 1) << 24
+    }
+
+    fn set_refresh_rate(sdclk: HertzU32) {
+        let fmc = unsafe { &(*pac::FMC::ptr()) };
+        let count = (REFRESH_PERIOD_NS as u64 * sdclk.raw() as u64 / 1_000_000_000) as u32 - 20;
+        fmc.sdrtr.write(|w| unsafe { w.bits(count << 1) });
+    }
+
+    fn command(mode: u32, parameters: u32) {
+        let fmc = unsafe { &(*pac::FMC::ptr()) };
+        // SDSR BUSY
+        while fmc.sdsr.read().bits() & (1 << 5) != 0 {}
+        fmc.sdcmr
+            .write(|w| unsafe { w.bits(mode | COMMAND_BANK1 | parameters) });
+    }
+}
+
+impl ClockChangeListener for Sdram {
+    /// Keeps the refresh rate, timings are only valid up to the clock at setup
+    fn clocks_changed(&mut self, clocks: &Clocks) {
+        let sdclk = clocks.hclk / 2;
+        assert!(sdclk <= self.sdclk, "SDRAM clock faster than at setup");
+        Self::set_refresh_rate(sdclk);
+    }
+}
@@ -0,0 +1,399 @@
+//! self_test
+//!
+//! Destructive SDRAM tests: walking ones on the data bus, address lines, and March C-
+//! over the tested range
+//!
+
+use crate::board::sdram::{Sdram, SDRAM_BASE, SDRAM_SIZE};
+use cortex_m::peripheral::SCB;
+
+/// Failures kept in a report, later ones are only counted
+pub const MAX_FAILURES: usize = 16;
+
+const PATTERN: u32 = 0xAAAA_AAAA;
+const ANTIPATTERN: u32 = !PATTERN;
+
+/// Part of the SDRAM covered by the march test, the bus tests always run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Coverage {
+    /// Byte offset of the tested range, a multiple of 4
+    pub offset: usize,
+    /// Bytes tested from `offset`, a multiple of 4
+    pub len: usize,
+    /// Tests every `stride`th word, 1 for every word
+    pub stride: usize,
+}
+
+impl Coverage {
+    /// Every word of the SDRAM
+    pub const FULL: Self = Self {
+        offset: 0,
+        len: SDRAM_SIZE,
+        stride: 1,
+    };
+    /// One word in 64, spreading over every row and bank
+    pub const QUICK: Self = Self {
+        offset: 0,
+        len: SDRAM_SIZE,
+        stride: 64,
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Test {
+    /// Walking ones, for data lines stuck or shorted
+    WalkingOnes,
+    /// Power of two offsets, for address lines stuck or shorted
+    AddressLines,
+    /// March C-, for cell and coupling faults
+    March,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Failure {
+    pub test: Test,
+    pub address: usize,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Failure {
+    /// Failing data bits
+    pub const fn bits(&self) -> u32 {
+        self.expected ^ self.actual
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct SelfTestReport {
+    pub coverage: Coverage,
+    /// Data bits failing at least once, over all tests
+    pub failing_bits: u32,
+    /// Word address lines found stuck or shorted, bit n for byte address line n + 2
+    pub failing_address_lines: u32,
+    /// Failures, recorded or not
+    pub failure_count: u32,
+    failures: [Option<Failure>; MAX_FAILURES],
+}
+
+impl SelfTestReport {
+    const fn new(coverage: Coverage) -> Self {
+        Self {
+            coverage,
+            failing_bits: 0,
+            failing_address_lines: 0,
+            failure_count: 0,
+            failures: [None; MAX_FAILURES],
+        }
+    }
+
+    pub const fn passed(&self) -> bool {
+        self.failure_count == 0
+    }
+
+    /// The first [`MAX_FAILURES`] failures
+    pub fn failures(&self) -> impl Iterator<Item = &Failure> {
+        self.failures.iter().flatten()
+    }
+
+    fn record(&mut self, failure: Failure) {
+        if let Some(slot) = self.failures.get_mut(self.failure_count as usize) {
+            *slot = Some(failure);
+        }
+        self.failure_count += 1;
+        self.failing_bits |= failure.bits();
+    }
+}
+
+/// Runs the tests on the SDRAM, with the data cache disabled meanwhile
+///
+/// # Safety
+///
+/// The content of the SDRAM is overwritten, nothing must use it, `.sdram` statics and
+/// heap included
+pub unsafe fn self_test(_sdram: &mut Sdram, coverage: Coverage) -> SelfTestReport {
+    assert!(
+        coverage.offset.is_multiple_of(4)
+            && coverage.len.is_multiple_of(4)
+            && coverage.offset + coverage.len <= SDRAM_SIZE
+            && coverage.stride > 0,
+        "invalid SDRAM test coverage"
+    );
+    let mut cp = cortex_m::Peripherals::steal();
+    let dcache = SCB::dcache_enabled();
+    if dcache {
+        cp.SCB.disable_dcache(&mut cp.CPUID);
+    }
+
+    let report = run(SDRAM_BASE as *mut u32, SDRAM_SIZE / 4, coverage);
+
+    if dcache {
+        cp.SCB.enable_dcache(&mut cp.CPUID);
+    }
+    report
+}
+
+/// Runs the tests on the `words` of memory at `base`
+///
+/// # Safety
+///
+/// `base` must be valid for `words` volatile reads and writes
+pub(crate) unsafe fn run(base: *mut u32, words: usize, coverage: Coverage) -> SelfTestReport {
+    run_on(&mut Memory { base }, words, coverage)
+}
+
+fn run_on(memory: &mut impl Words, words: usize, coverage: Coverage) -> SelfTestReport {
+    let mut report = SelfTestReport::new(coverage);
+    walking_ones(memory, &mut report);
+    address_lines(memory, words, &mut report);
+    march(
+        memory,
+        coverage.offset / 4,
+        coverage.len / 4,
+        coverage.stride,
+        &mut report,
+    );
+    report
+}
+
+/// Memory under test, by word index
+trait Words {
+    fn write(&mut self, index: usize, value: u32);
+
+    fn read(&mut self, index: usize) -> u32;
+
+    /// Address reported for the word at `index`
+    fn address(&self, index: usize) -> usize;
+
+    /// Reads the word at `index`, recording a failure if it is not `expected`
+    fn check(
+        &mut self,
+        test: Test,
+        index: usize,
+        expected: u32,
+        report: &mut SelfTestReport,
+    ) -> bool {
+        let actual = self.read(index);
+        if actual != expected {
+            report.record(Failure {
+                test,
+                address: self.address(index),
+                expected,
+                actual,
+            });
+        }
+        actual == expected
+    }
+}
+
+struct Memory {
+    base: *mut u32,
+}
+
+impl Words for Memory {
+    fn write(&mut self, index: usize, value: u32) {
+        unsafe { self.base.add(index).write_volatile(value) };
+    }
+
+    fn read(&mut self, index: usize) -> u32 {
+        unsafe { self.base.add(index).read_volatile() }
+    }
+
+    fn address(&self, index: usize) -> usize {
+        self.base as usize + index * 4
+    }
+}
+
+fn walking_ones(memory: &mut impl Words, report: &mut SelfTestReport) {
+    for bit in 0..u32::BITS {
+        memory.write(0, 1 << bit);
+        memory.check(Test::WalkingOnes, 0, 1 << bit, report);
+    }
+}
+
+/// Writes the pattern at every power of two word offset, then the antipattern at each
+/// in turn, an address line being faulty where another offset changes
+fn address_lines(memory: &mut impl Words, words: usize, report: &mut SelfTestReport) {
+    let lines = words.ilog2();
+    let offsets = || (0..lines).map(|line| (line, 1usize << line));
+
+    for (_, offset) in offsets() {
+        memory.write(offset, PATTERN);
+    }
+    // Stuck high
+    memory.write(0, ANTIPATTERN);
+    for (line, offset) in offsets() {
+        if !memory.check(Test::AddressLines, offset, PATTERN, report) {
+            report.failing_address_lines |= 1 << line;
+        }
+    }
+    memory.write(0, PATTERN);
+    // Stuck low or shorted
+    for (line, offset) in offsets() {
+        memory.write(offset, ANTIPATTERN);
+        if !memory.check(Test::AddressLines, 0, PATTERN, report) {
+            report.failing_address_lines |= 1 << line;
+        }
+        for (other_line, other) in offsets().filter(|&(other_line, _)| other_line != line) {
+            if !memory.check(Test::AddressLines, other, PATTERN, report) {
+                report.failing_address_lines |= 1 << line | 1 << other_line;
+            }
+        }
+        memory.write(offset, PATTERN);
+    }
+}
+
+/// March C-: ⇕(w0) ⇑(r0,w1) ⇑(r1,w0) ⇓(r0,w1) ⇓(r1,w0) ⇕(r0)
+fn march(
+    memory: &mut impl Words,
+    start: usize,
+    len: usize,
+    stride: usize,
+    report: &mut SelfTestReport,
+) {
+    let up = || (start..start + len).step_by(stride);
+    for index in up() {
+        memory.write(index, 0);
+    }
+
+    let mut element = |indexes: &mut dyn Iterator<Item = usize>,
+                       read: u32,
+                       write: Option<u32>,
+                       report: &mut SelfTestReport| {
+        for index in indexes {
+            memory.check(Test::March, index, read, report);
+            if let Some(write) = write {
+                memory.write(index, write);
+            }
+        }
+    };
+    element(&mut up(), 0, Some(u32::MAX), report);
+    element(&mut up(), u32::MAX, Some(0), report);
+    element(&mut up().rev(), 0, Some(u32::MAX), report);
+    element(&mut up().rev(), u32::MAX, Some(0), report);
+    element(&mut up(), 0, None, report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: usize = 1024;
+    const ALL: Coverage = Coverage {
+        offset: 0,
+        len: WORDS * 4,
+        stride: 1,
+    };
+
+    /// Memory with a data bit stuck high, or a word address line stuck low
+    struct Faulty {
+        words: Vec<u32>,
+        stuck_bits: u32,
+        stuck_line: Option<u32>,
+    }
+
+    impl Faulty {
+        fn new() -> Self {
+            Self {
+                words: vec![0; WORDS],
+                stuck_bits: 0,
+                stuck_line: None,
+            }
+        }
+
+        fn index(&self, index: usize) -> usize {
+            self.stuck_line.map_or(index, |line| index & !(1 << line))
+        }
+    }
+
+    impl Words for Faulty {
+        fn write(&mut self, index: usize, value: u32) {
+            let index = self.index(index);
+            self.words[index] = value;
+        }
+
+        fn read(&mut self, index: usize) -> u32 {
+            self.words[self.index(index)] | self.stuck_bits
+        }
+
+        fn address(&self, index: usize) -> usize {
+            index * 4
+        }
+    }
+
+    #[test]
+    fn clean_pass() {
+        let mut words = vec![0x5555_5555; WORDS];
+        let report = unsafe { run(words.as_mut_ptr(), WORDS, ALL) };
+        assert!(report.passed());
+        assert_eq!(report.failing_bits, 0);
+        assert_eq!(report.failing_address_lines, 0);
+        assert_eq!(report.failures().count(), 0);
+    }
+
+    #[test]
+    fn stuck_data_bit() {
+        let mut memory = Faulty::new();
+        memory.stuck_bits = 1 << 5;
+        let report = run_on(&mut memory, WORDS, ALL);
+        assert!(!report.passed());
+        assert_eq!(report.failing_bits, 1 << 5);
+        // PATTERN has bit 5 set
+        assert_eq!(report.failing_address_lines, 0);
+    }
+
+    #[test]
+    fn aliased_address_line() {
+        let mut memory = Faulty::new();
+        memory.stuck_line = Some(3);
+        let report = run_on(&mut memory, WORDS, ALL);
+        assert!(!report.passed());
+        assert_eq!(report.failing_address_lines, 1 << 3);
+        assert!(report
+            .failures()
+            .any(|failure| failure.test == Test::AddressLines && failure.address == 8 * 4));
+    }
+
+    #[test]
+    fn partial_coverage() {
+        const SENTINEL: u32 = 0x5555_5555;
+        let coverage = Coverage {
+            offset: 300 * 4,
+            len: 200 * 4,
+            stride: 4,
+        };
+        let mut words = vec![SENTINEL; WORDS];
+        let report = unsafe { run(words.as_mut_ptr(), WORDS, coverage) };
+        assert!(report.passed());
+        assert_eq!(report.coverage, coverage);
+
+        for (index, word) in words.iter().enumerate() {
+            let bus_test = index == 0 || index.is_power_of_two();
+            let marched = (300..500).contains(&index) && (index - 300) % 4 == 0;
+            if marched {
+                assert_eq!(*word, 0, "word {index}");
+            } else if !bus_test {
+                assert_eq!(*word, SENTINEL, "word {index}");
+            }
+        }
+    }
+
+    #[test]
+    fn failures_capped() {
+        let mut memory = Faulty::new();
+        memory.stuck_bits = 1 << 5;
+        let report = run_on(&mut memory, WORDS, ALL);
+        // 31 walking ones, and the three march elements reading zeros
+        assert_eq!(report.failure_count, 31 + 3 * WORDS as u32);
+        assert_eq!(report.failures().count(), MAX_FAILURES);
+        assert_eq!(
+            report.failures().next(),
+            Some(&Failure {
+                test: Test::WalkingOnes,
+                address: 0,
+                expected: 1 << 0,
+                actual: 1 << 0 | 1 << 5,
+            })
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! setup
+//!
+//! Board bring-up shared by the blocking and async board variants
+//!
+
+use crate::board::{
+    backup::Backup,
+    clocks,
+    compat::Compat,
+    led_pwm::LedPwmTimer,
+    qspi::{self, QuadSpi},
+    sdram::Sdram,
+    watchdog::Watchdog,
+    BoardConfig, VoltageScale,
+};
+use crate::sys::{
+    cache::{Attributes, RamRegion},
+    ClockSource,
+};
+use crate::{
+    drivers::{led, pmic, qspi_flash::QspiFlash},
+    hal, sys,
+};
+use defmt::debug;
+use hal::{
+    gpio::{Output, Pin, PinState, PushPull, Speed},
+    i2c::I2c,
+    pac,
+    prelude::*,
+    rcc::ResetEnable,
+    usb_hs::{UsbBus, USB1_ULPI},
+};
+
+type DigitalOutputPin<const P: char, const N: u8> = Pin<P, N, Output<PushPull>>;
+pub type LedRed = led::Led<Compat<DigitalOutputPin<'K', 5>>>;
+pub type LedGreen = led::Led<Compat<DigitalOutputPin<'K', 6>>>;
+pub type LedBlue = led::Led<Compat<DigitalOutputPin<'K', 7>>>;
+pub type RgbLedImpl = led::RgbLed<
+    Compat<DigitalOutputPin<'K', 5>>,
+    Compat<DigitalOutputPin<'K', 6>>,
+    Compat<DigitalOutputPin<'K', 7>>,
+>;
+pub type UsbPer = USB1_ULPI;
+pub type UsbBusImpl = UsbBus<UsbPer>;
+pub type I2c1 = I2c<pac::I2C1>;
+pub type QspiFlashImpl = QspiFlash<QuadSpi>;
+
+pub struct Resources {
+    pub led_red: LedRed,
+    pub led_green: LedGreen,
+    pub led_blue: LedBlue,
+    pub led_pwm_timer: LedPwmTimer,
+    pub usb: Option<UsbPer>,
+    pub i2c1: I2c1,
+    pub backup: Backup,
+    pub reset_reason: sys::ResetReason,
+    pub watchdog: Watchdog,
+    pub sdram: Option<Sdram>,
+    pub qspi_flash: Option<QspiFlashImpl>,
+    pub clocks: sys::Clocks,
+}
+
+pub fn setup(config: &BoardConfig) -> Resources {
+    // Load the code run from ITCM
+    sys::itcm::init();
+
+    // Read before anything can reset again
+    let reset_reason = sys::ResetReason::take();
+
+    // Reset the state left by the bootloader and enable external oscillator as HSE
+    // source (25 MHz)
+    let clk = sys::handover();
+    if config.source == ClockSource::Hse {
+        clk.enable_ext_clock();
+    }
+    let dp = pac::Peripherals::take().unwrap();
+
+    // Backup SRAM, its clock was gated by the handover
+    let backup = Backup::new();
+
+    // Configure power domains and clock tree
+    let pwr = dp.PWR.constrain();
+    let pwr = match config.vos {
+        VoltageScale::Vos0 => pwr.vos0(&dp.SYSCFG),
+        VoltageScale::Vos1 => pwr.vos1(),
+        VoltageScale::Vos2 => pwr.vos2(),
+        VoltageScale::Vos3 => pwr.vos3(),
+    };
+    let pwrcfg = pwr.freeze();
+    let mut rcc = dp
+        .RCC
+        .constrain()
+        .sys_ck(config.sys_ck)
+        .hclk(config.hclk)
+        .pll1_strategy(config.pll1_strategy);
+    if config.source == ClockSource::Hse {
+        rcc = rcc.use_hse(sys::HSE_FREQUENCY).bypass_hse();
+    }
+    if let Some(pclk) = config.pclk {
+        rcc = rcc.pclk1(pclk).pclk2(pclk).pclk3(pclk).pclk4(pclk);
+    }
+    let ccdr = rcc.freeze(pwrcfg, &dp.SYSCFG);
+
+    if config.source == ClockSource::Hse {
+        debug_assert_eq!(sys::Clk::get_source(), Some(sys::ClkSource::Pll1));
+        debug_assert_eq!(sys::Clk::get_pll_source(), sys::PllSourceVariant::Hse);
+        if config.hse_css {
+            sys::css::enable();
+        }
+    }
+    if sys::css::hse_failed() {
+        defmt::warn!("HSE failed before the last reset");
+    }
+    let report = sys::ClockReport::capture();
+    debug!("{}", report);
+    debug!("Reset reason: {}", reset_reason);
+
+    // MPU and caches, the SDRAM mapped as normal memory rather than the default device
+    // memory of the FMC, the QSPI flash inaccessible until memory-mapped
+    let sdram_region = config
+        .sdram
+        .then(|| RamRegion::Sdram.mpu_region(Attributes::WriteBack));
+    let qspi_region = config.qspi_flash.then_some(qspi::UNMAPPED_REGION);
+    sys::cache::configure_mpu(
+        sdram_region.into_iter().chain(qspi_region).chain(
+            config
+                .non_cacheable_regions()
+                .map(|region| region.mpu_region(Attributes::NonCacheable)),
+        ),
+    );
+    if config.caches {
+        sys::cache::enable_caches();
+    }
+    assert!(
+        !config.usb || ccdr.clocks.hclk() >= sys::USB_HS_MIN_HCLK,
+        "USB HS needs a faster AHB clock"
+    );
+
+    // GPIOs
+    let (gpioa, gpiob, gpioc, gpiod, gpioe, gpiof, gpiog, gpioh, gpioi, gpioj) = {
+        (
+            dp.GPIOA.split(ccdr.peripheral.GPIOA),
+            dp.GPIOB.split(ccdr.peripheral.GPIOB),
+            dp.GPIOC.split(ccdr.peripheral.GPIOC),
+            dp.GPIOD.split(ccdr.peripheral.GPIOD),
+            dp.GPIOE.split(ccdr.peripheral.GPIOE),
+            dp.GPIOF.split(ccdr.peripheral.GPIOF),
+            dp.GPIOG.split(ccdr.peripheral.GPIOG),
+            dp.GPIOH.split_without_reset(ccdr.peripheral.GPIOH), // Do not do a reset since external oscillator is enabled by GPIOH1
+            dp.GPIOI.split(ccdr.peripheral.GPIOI),
+            dp.GPIOJ.split(ccdr.peripheral.GPIOJ),
+        )
+    };
+    // Enable ULPI transceiver (GPIOJ4), or hold it in reset when USB is not used
+    let ulpi_state = if config.usb {
+        PinState::High
+    } else {
+        PinState::Low
+    };
+    let _ulpi_reset = gpioj.pj4.into_push_pull_output_in_state(ulpi_state);
+
+    let usb = if config.usb {
+        Some(USB1_ULPI::new(
+            dp.OTG1_HS_GLOBAL,
+            dp.OTG1_HS_DEVICE,
+            dp.OTG1_HS_PWRCLK,
+            gpioa.pa5.into_alternate(),
+            gpioi.pi11.into_alternate(),
+            gpioh.ph4.into_alternate(),
+            gpioc.pc0.into_alternate(),
+            gpioa.pa3.into_alternate(),
+            gpiob.pb0.into_alternate(),
+            gpiob.pb1.into_alternate(),
+            gpiob.pb10.into_alternate(),
+            gpiob.pb11.into_alternate(),
+            gpiob.pb12.into_alternate(),
+            gpiob.pb13.into_alternate(),
+            gpiob.pb5.into_alternate(),
+            ccdr.peripheral.USB1OTG,
+            &ccdr.clocks,
+        ))
+    } else {
+        None
+    };
+
+    // SDRAM on the FMC
+    let sdram = if config.sdram {
+        macro_rules! fmc_pins {
+            ($($pin:expr),* $(,)?) => {
+                $(let _ = $pin.into_alternate::<12>().speed(Speed::VeryHigh);)*
+            };
+        }
+        fmc_pins!(
+            gpiod.pd0, gpiod.pd1, gpiod.pd8, gpiod.pd9, gpiod.pd10, gpiod.pd14, gpiod.pd15,
+            gpioe.pe0, gpioe.pe1, gpioe.pe7, gpioe.pe8, gpioe.pe9, gpioe.pe10, gpioe.pe11,
+            gpioe.pe12, gpioe.pe13, gpioe.pe14, gpioe.pe15, gpiof.pf0, gpiof.pf1, gpiof.pf2,
+            gpiof.pf3, gpiof.pf4, gpiof.pf5, gpiof.pf11, gpiof.pf12, gpiof.pf13, gpiof.pf14,
+            gpiof.pf15, gpiog.pg0, gpiog.pg1, gpiog.pg4, gpiog.pg5, gpiog.pg8, gpiog.pg15,
+            gpioh.ph2, gpioh.ph3, gpioh.ph5,
+        );
+        ccdr.peripheral.FMC.enable().reset();
+        let sdram = Sdram::init(ccdr.clocks.hclk(), ccdr.clocks.sys_ck());
+        #[cfg(feature = "sdram-heap")]
+        sdram.init_heap();
+        Some(sdram)
+    } else {
+        None
+    };
+    #[cfg(feature = "sdram-heap")]
+    assert!(sdram.is_some(), "The sdram-heap feature needs the SDRAM");
+
+    // QSPI flash on QUADSPI bank 1
+    let qspi_flash = if config.qspi_flash {
+        let _ = gpiof.pf10.into_alternate::<9>().speed(Speed::VeryHigh);
+        let _ = gpiog.pg6.into_alternate::<10>().speed(Speed::VeryHigh);
+        let _ = gpiod.pd11.into_alternate::<9>().speed(Speed::VeryHigh);
+        let _ = gpiod.pd12.into_alternate::<9>().speed(Speed::VeryHigh);
+        let _ = gpiof.pf7.into_alternate::<9>().speed(Speed::VeryHigh);
+        let _ = gpiod.pd13.into_alternate::<9>().speed(Speed::VeryHigh);
+        ccdr.peripheral.QSPI.enable().reset();
+        let mut qspi_flash = QspiFlash::new(QuadSpi::init(ccdr.clocks.hclk()));
+        match qspi_flash.init() {
+            Ok(()) => Some(qspi_flash),
+            Err(error) => {
+                defmt::error!("QSPI flash: {}", error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // User LEDs
+    let gpiok = dp.GPIOK.split(ccdr.peripheral.GPIOK);
+    let (output_k5, output_k6, output_k7) = (
+        gpiok.pk5.into_push_pull_output_in_state(PinState::High),
+        gpiok.pk6.into_push_pull_output_in_state(PinState::High),
+        gpiok.pk7.into_push_pull_output_in_state(PinState::High),
+    );
+
+    let led_red = led::Led::new(Compat::new(output_k5));
+    let led_green = led::Led::new(Compat::new(output_k6));
+    let led_blue = led::Led::new(Compat::new(output_k7));
+
+    // LED software PWM tick, started by the application which binds the TIM7 interrupt
+    let led_pwm_timer = LedPwmTimer::new(dp.TIM7, ccdr.peripheral.TIM7, &ccdr.clocks);
+
+    // I2C1, shared with the PMIC
+    let (i2c1_scl, i2c1_sda) = (
+        gpiob.pb6.into_alternate_open_drain(),
+        gpiob.pb7.into_alternate_open_drain(),
+    );
+    clocks::set_i2c1_frequency(config.i2c1_frequency);
+    let i2c1 = dp.I2C1.i2c(
+        (i2c1_scl, i2c1_sda),
+        config.i2c1_frequency,
+        ccdr.peripheral.I2C1,
+        &ccdr.clocks,
+    );
+
+    // PMIC, checked over the blocking bus before either board variant wraps it
+    let mut i2c1 = Compat::new(i2c1);
+    match pmic::Pmic::new(&mut i2c1).verify_device_id() {
+        Ok(id) => debug!("PMIC device ID: {:X}", id),
+        Err(err) => debug!("PMIC device ID error: {}", err),
+    }
+    let i2c1 = i2c1.into_inner();
+
+    // Watchdog, started last so bring-up does not count against its timeout
+    let mut watchdog = Watchdog::new(reset_reason);
+    if let Some(starved) = watchdog.starved() {
+        defmt::warn!("Watchdog starved by client {}", starved);
+    }
+    if let Some(timeout) = config.watchdog {
+        watchdog.start(timeout);
+    }
+
+    Resources {
+        led_red,
+        led_green,
+        led_blue,
+        led_pwm_timer,
+        usb,
+        i2c1,
+        backup,
+        reset_reason,
+        watchdog,
+        sdram,
+        qspi_flash,
+        clocks: report.clocks(),
+    }
+}
@@ -0,0 +1,232 @@
+//! watchdog
+//!
+//! IWDG1 independent watchdog, fed once every registered client checked in within
+//! its deadline
+//! The client starving the watchdog is recorded in backup SRAM, to be reported after
+//! the reset
+//!
+
+use crate::sys::{cache, Core, ResetReason};
+use core::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+use fugit::MillisDurationU32;
+use stm32h7xx_hal::pac;
+
+/// Maximum number of check-in clients
+pub const MAX_CLIENTS: usize = 8;
+
+/// LSI clocking the watchdog, nominally 32 kHz
+const LSI_FREQUENCY_HZ: u32 = 32_000;
+const MAX_RELOAD: u32 = 0xFFF;
+/// Largest PR value, dividing LSI by 256
+const MAX_PRESCALER: u32 = 6;
+
+const KEY_START: u32 = 0xCCCC;
+const KEY_UNLOCK: u32 = 0x5555;
+const KEY_FEED: u32 = 0xAAAA;
+
+const STARVED_MAGIC: u32 = 0x57A7_0ED0;
+
+/// Starving client, at a fixed offset of the backup SRAM in memory.x so it stays in
+/// place across firmware updates
+#[link_section = ".bsram.watchdog"]
+static mut STARVED: MaybeUninit<[u32; 2]> = MaybeUninit::uninit();
+
+/// Deadline of each client in ms, 0 when not registered
+static DEADLINES: [AtomicU32; MAX_CLIENTS] = [const { AtomicU32::new(0) }; MAX_CLIENTS];
+/// Time of the last check-in of each client in ms
+static CHECK_INS: [AtomicU32; MAX_CLIENTS] = [const { AtomicU32::new(0) }; MAX_CLIENTS];
+static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Client ID, the index of its registration
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ClientId(pub u8);
+
+/// Handle of a registered client, moved to the task checking in
+#[derive(Debug)]
+pub struct CheckIn {
+    id: ClientId,
+}
+
+impl CheckIn {
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    /// Reports the client alive at `now`, in ms since any fixed epoch shared with
+    /// [`Watchdog::poll`]
+    pub fn check_in(&self, now: MillisDurationU32) {
+        CHECK_INS[self.id.0 as usize].store(now.ticks(), Ordering::Release);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum WatchdogError {
+    /// All [`MAX_CLIENTS`] are registered
+    TooManyClients,
+    /// A client missed its deadline, the watchdog is no longer fed
+    Starved(ClientId),
+}
+
+pub struct Watchdog {
+    started: bool,
+    starved: Option<ClientId>,
+}
+
+impl Watchdog {
+    /// Takes the client recorded before a watchdog reset, the backup domain being
+    /// unlocked
+    pub(crate) fn new(reset_reason: ResetReason) -> Self {
+        let record = Self::record();
+        let recorded = unsafe { record.read_volatile() } == STARVED_MAGIC;
+        let starved = (recorded && reset_reason == ResetReason::IndependentWatchdog(Core::Cm7))
+            .then(|| ClientId(unsafe { record.add(1).read_volatile() } as u8));
+        unsafe { record.write_volatile(0) };
+        cache::clean_dcache(unsafe { &*ptr::addr_of!(STARVED) });
+        Self {
+            started: false,
+            starved,
+        }
+    }
+
+    /// Starts IWDG1, resetting the system if not fed within `timeout`, from 1 ms to
+    /// about 32 s
+    ///
+    /// Once started, the watchdog can only be stopped by a reset
+    pub fn start(&mut self, timeout: MillisDurationU32) {
+        let (prescaler, reload) = Self::timing(timeout);
+        let iwdg = unsafe { &(*pac::IWDG1::ptr()) };
+        unsafe {
+            iwdg.kr.write(|w| w.bits(KEY_START));
+            iwdg.kr.write(|w| w.bits(KEY_UNLOCK));
+            iwdg.pr.write(|w| w.bits(prescaler));
+            iwdg.rlr.write(|w| w.bits(reload));
+        }
+        while iwdg.sr.read().bits() != 0 {}
+        self.feed();
+        self.started = true;
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Reloads the watchdog counter, regardless of the clients
+    pub fn feed(&mut self) {
+        let iwdg = unsafe { &(*pac::IWDG1::ptr()) };
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_FEED) });
+    }
+
+    /// Client that starved the watchdog before the last reset
+    pub fn starved(&self) -> Option<ClientId> {
+        self.starved
+    }
+
+    /// Registers a client that must check in within `deadline`, from `now`
+    pub fn register(
+        &mut self,
+        deadline: MillisDurationU32,
+        now: MillisDurationU32,
+    ) -> Result<CheckIn, WatchdogError> {
+        let index = CLIENTS.load(Ordering::Acquire);
+        if index == MAX_CLIENTS {
+            return Err(WatchdogError::TooManyClients);
+        }
+        CHECK_INS[index].store(now.ticks(), Ordering::Release);
+        DEADLINES[index].store(deadline.ticks().max(1), Ordering::Release);
+        CLIENTS.store(index + 1, Ordering::Release);
+        Ok(CheckIn {
+            id: ClientId(index as u8),
+        })
+    }
+
+    /// Feeds the watchdog if every client checked in within its deadline, to be called
+    /// periodically, more often than the timeout
+    ///
+    /// Otherwise the first late client is recorded in backup SRAM and the watchdog is
+    /// left to reset the system. The record is cleared once every client is back on
+    /// time and the watchdog fed again, so a later reset is not blamed on it.
+    pub fn poll(&mut self, now: MillisDurationU32) -> Result<(), WatchdogError> {
+        let late = (0..CLIENTS.load(Ordering::Acquire)).find(|&index| {
+            let check_in = CHECK_INS[index].load(Ordering::Acquire);
+            let deadline = DEADLINES[index].load(Ordering::Acquire);
+            is_late(now.ticks(), check_in, deadline)
+        });
+        match late {
+            None => {
+                self.feed();
+                Self::clear_starved();
+                Ok(())
+            }
+            Some(index) => {
+                let id = ClientId(index as u8);
+                Self::record_starved(id);
+                Err(WatchdogError::Starved(id))
+            }
+        }
+    }
+
+    /// PR and RLR values for `timeout`, with the finest prescaler
+    fn timing(timeout: MillisDurationU32) -> (u32, u32) {
+        let ticks = timeout.ticks() as u64 * LSI_FREQUENCY_HZ as u64 / 1000;
+        let prescaler = (0..=MAX_PRESCALER)
+            .find(|prescaler| ticks.div_ceil(4 << prescaler) <= MAX_RELOAD as u64 + 1)
+            .expect("watchdog timeout too long");
+        let reload = ticks.div_ceil(4 << prescaler).max(1) - 1;
+        (prescaler, reload as u32)
+    }
+
+    fn record() -> *mut u32 {
+        ptr::addr_of_mut!(STARVED) as *mut u32
+    }
+
+    fn clear_starved() {
+        let record = Self::record();
+        if unsafe { record.read_volatile() } == STARVED_MAGIC {
+            unsafe { record.write_volatile(0) };
+            cache::clean_dcache(unsafe { &*ptr::addr_of!(STARVED) });
+        }
+    }
+
+    fn record_starved(id: ClientId) {
+        let record = Self::record();
+        unsafe {
+            record.add(1).write_volatile(id.0 as u32);
+            record.write_volatile(STARVED_MAGIC);
+        }
+        cache::clean_dcache(unsafe { &*ptr::addr_of!(STARVED) });
+    }
+}
+
+/// Whether `check_in` is more than `deadline` before `now`, across the wrap of the ms
+/// counter
+///
+/// A check-in after `now`, by a task preempting the caller of [`Watchdog::poll`]
+/// after it took the time, is on time.
+fn is_late(now: u32, check_in: u32, deadline: u32) -> bool {
+    (now.wrapping_sub(check_in) as i32) > deadline as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_check_in() {
+        assert!(!is_late(1000, 900, 100));
+        assert!(is_late(1000, 899, 100));
+        // Across the wrap of the counter
+        assert!(!is_late(50, u32::MAX - 49, 100));
+        assert!(is_late(50, u32::MAX - 50, 100));
+    }
+
+    #[test]
+    fn check_in_after_now() {
+        assert!(!is_late(1000, 1001, 100));
+        assert!(!is_late(1000, 1500, 100));
+        assert!(!is_late(u32::MAX, 10, 100));
+    }
+}
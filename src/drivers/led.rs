@@ -1,5 +1,9 @@
 //! led
 
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_v1::digital::{OutputPin, StatefulOutputPin};
+use fugit::MillisDurationU32;
+
 pub struct Led<PIN> {
     pin: PIN,
 }
@@ -8,9 +12,13 @@ impl<PIN> Led<PIN> {
     pub fn new(pin: PIN) -> Self {
         Self { pin }
     }
+
+    pub fn release(self) -> PIN {
+        self.pin
+    }
 }
 
-impl<PIN: embedded_hal_v0::digital::v2::OutputPin> Led<PIN> {
+impl<PIN: OutputPin> Led<PIN> {
     pub fn on(&mut self) {
         let _ = self.pin.set_low();
     }
@@ -20,8 +28,506 @@ impl<PIN: embedded_hal_v0::digital::v2::OutputPin> Led<PIN> {
     }
 }
 
-impl<PIN: embedded_hal_v0::digital::v2::ToggleableOutputPin> Led<PIN> {
+impl<PIN: StatefulOutputPin> Led<PIN> {
     pub fn toggle(&mut self) {
         let _ = self.pin.toggle();
     }
+
+    pub fn is_on(&mut self) -> bool {
+        self.pin.is_set_low().unwrap_or(false)
+    }
+}
+
+/// Number of ticks in a software PWM period, the levels the 8-bit brightness is
+/// rounded to, brightness 255 keeping the LED always on
+pub const PWM_STEPS: u8 = 64;
+
+/// LED with 8-bit brightness, dimmed by software PWM
+///
+/// [`PwmLed::tick`] must be called at a fixed rate, the PWM frequency being the tick
+/// rate divided by [`PWM_STEPS`]
+pub struct PwmLed<PIN> {
+    led: Led<PIN>,
+    brightness: u8,
+    /// Ticks on per period
+    duty: u8,
+    phase: u8,
+}
+
+impl<PIN> PwmLed<PIN> {
+    pub fn new(led: Led<PIN>) -> Self {
+        Self {
+            led,
+            brightness: 0,
+            duty: 0,
+            phase: 0,
+        }
+    }
+
+    pub fn release(self) -> Led<PIN> {
+        self.led
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Sets the brightness, output with the [`PWM_STEPS`] levels of the PWM, so
+    /// about 4 consecutive values give the same duty cycle
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.duty = ((brightness as u16 * PWM_STEPS as u16 + 127) / 255) as u8;
+    }
+}
+
+impl<PIN: OutputPin> PwmLed<PIN> {
+    pub fn tick(&mut self) {
+        if self.phase < self.duty {
+            self.led.on();
+        } else {
+            self.led.off();
+        }
+        self.phase += 1;
+        if self.phase == PWM_STEPS {
+            self.phase = 0;
+        }
+    }
+}
+
+/// 8-bit per channel color
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub const OFF: Self = Self(0, 0, 0);
+    pub const RED: Self = Self(255, 0, 0);
+    pub const GREEN: Self = Self(0, 255, 0);
+    pub const BLUE: Self = Self(0, 0, 255);
+    pub const YELLOW: Self = Self(255, 255, 0);
+    pub const CYAN: Self = Self(0, 255, 255);
+    pub const MAGENTA: Self = Self(255, 0, 255);
+    pub const WHITE: Self = Self(255, 255, 255);
+    pub const ORANGE: Self = Self(255, 128, 0);
+
+    /// Scales every channel by `level`, 255 keeping the color unchanged
+    pub const fn scaled(self, level: u8) -> Self {
+        const fn scale(channel: u8, level: u8) -> u8 {
+            ((channel as u16 * level as u16 + 127) / 255) as u8
+        }
+        Self(
+            scale(self.0, level),
+            scale(self.1, level),
+            scale(self.2, level),
+        )
+    }
+}
+
+/// RGB LED made of three software PWM driven LEDs
+pub struct RgbLed<R, G, B> {
+    red: PwmLed<R>,
+    green: PwmLed<G>,
+    blue: PwmLed<B>,
+}
+
+impl<R, G, B> RgbLed<R, G, B> {
+    pub fn new(red: Led<R>, green: Led<G>, blue: Led<B>) -> Self {
+        Self {
+            red: PwmLed::new(red),
+            green: PwmLed::new(green),
+            blue: PwmLed::new(blue),
+        }
+    }
+
+    pub fn release(self) -> (Led<R>, Led<G>, Led<B>) {
+        (
+            self.red.release(),
+            self.green.release(),
+            self.blue.release(),
+        )
+    }
+
+    pub fn color(&self) -> Rgb {
+        Rgb(
+            self.red.brightness(),
+            self.green.brightness(),
+            self.blue.brightness(),
+        )
+    }
+
+    /// Sets the color, each channel output with the [`PWM_STEPS`] levels of the PWM
+    pub fn set_color(&mut self, color: Rgb) {
+        self.red.set_brightness(color.0);
+        self.green.set_brightness(color.1);
+        self.blue.set_brightness(color.2);
+    }
+}
+
+impl<R: OutputPin, G: OutputPin, B: OutputPin> RgbLed<R, G, B> {
+    pub fn tick(&mut self) {
+        self.red.tick();
+        self.green.tick();
+        self.blue.tick();
+    }
+}
+
+/// Declarative LED sequence, see [`Sequencer`] to play it on a LED
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Pattern {
+    Off,
+    On,
+    /// Blinks with a 50 % duty cycle at the given frequency in Hz
+    Blink(u8),
+    /// Double beat followed by a pause, once per second
+    Heartbeat,
+    /// `count` short pulses followed by a pause of `pause_ms`
+    Pulses {
+        count: u8,
+        pause_ms: u16,
+    },
+    /// Morse SOS, ... --- ...
+    Sos,
+    /// Triangle ramp from off to full brightness and back over `period_ms`
+    Fade {
+        period_ms: u16,
+    },
+}
+
+/// Longest time a static pattern waits before being polled again
+const IDLE_MS: u32 = 1000;
+const HEARTBEAT: [(u8, u32); 4] = [(255, 100), (0, 100), (255, 100), (0, 700)];
+const PULSE_MS: u32 = 150;
+const SOS_UNIT_MS: u32 = 200;
+const SOS: [(u8, u32); 18] = [
+    (255, 1),
+    (0, 1),
+    (255, 1),
+    (0, 1),
+    (255, 1),
+    (0, 3),
+    (255, 3),
+    (0, 1),
+    (255, 3),
+    (0, 1),
+    (255, 3),
+    (0, 3),
+    (255, 1),
+    (0, 1),
+    (255, 1),
+    (0, 1),
+    (255, 1),
+    (0, 7),
+];
+const FADE_STEP_MS: u32 = 10;
+
+impl Pattern {
+    /// Returns the brightness `elapsed_ms` after the start of the pattern, and the time
+    /// in ms until it next changes
+    pub fn level(&self, elapsed_ms: u32) -> (u8, u32) {
+        match *self {
+            Pattern::Off | Pattern::Blink(0) => (0, IDLE_MS),
+            Pattern::On => (255, IDLE_MS),
+            Pattern::Blink(hz) => {
+                let half = (500 / hz as u32).max(1);
+                step_at([(255, half), (0, half)].into_iter(), elapsed_ms)
+            }
+            Pattern::Heartbeat => step_at(HEARTBEAT.into_iter(), elapsed_ms),
+            Pattern::Pulses { count, pause_ms } => step_at(
+                (0..count)
+                    .flat_map(|_| [(255, PULSE_MS), (0, PULSE_MS)])
+                    .chain(core::iter::once((0, pause_ms as u32))),
+                elapsed_ms,
+            ),
+            Pattern::Sos => step_at(
+                SOS.into_iter()
+                    .map(|(level, units)| (level, units * SOS_UNIT_MS)),
+                elapsed_ms,
+            ),
+            Pattern::Fade { period_ms: 0 } => (0, IDLE_MS),
+            Pattern::Fade { period_ms } => {
+                let period = period_ms as u32;
+                let t = elapsed_ms % period;
+                let ramp = if t < period / 2 { t } else { period - t };
+                let level = (ramp * 2 * 255 / period).min(255) as u8;
+                (level, FADE_STEP_MS.min(period - t))
+            }
+        }
+    }
+}
+
+/// Looks up the step active at `elapsed_ms` in a repeating sequence of
+/// `(level, duration_ms)` steps
+fn step_at(steps: impl Iterator<Item = (u8, u32)> + Clone, elapsed_ms: u32) -> (u8, u32) {
+    let period: u32 = steps.clone().map(|(_, duration)| duration).sum();
+    if period == 0 {
+        return (0, IDLE_MS);
+    }
+    let mut t = elapsed_ms % period;
+    for (level, duration) in steps {
+        if t < duration {
+            return (level, duration - t);
+        }
+        t -= duration;
+    }
+    unreachable!()
+}
+
+/// Output a [`Pattern`] can be played on
+pub trait Dimmable {
+    fn set_level(&mut self, level: u8);
+}
+
+impl<PIN: OutputPin> Dimmable for Led<PIN> {
+    /// Switches the LED on from half brightness
+    fn set_level(&mut self, level: u8) {
+        if level >= 128 {
+            self.on();
+        } else {
+            self.off();
+        }
+    }
+}
+
+impl<PIN> Dimmable for PwmLed<PIN> {
+    /// Rounds the level to the [`PWM_STEPS`] levels of the PWM
+    fn set_level(&mut self, level: u8) {
+        self.set_brightness(level);
+    }
+}
+
+/// Plays a [`Pattern`] on a LED, driven either by [`Sequencer::poll`] from any time
+/// source or by [`Sequencer::run`] with an async delay
+pub struct Sequencer<L> {
+    led: L,
+    pattern: Pattern,
+    start: MillisDurationU32,
+}
+
+impl<L: Dimmable> Sequencer<L> {
+    pub fn new(led: L, pattern: Pattern) -> Self {
+        Self {
+            led,
+            pattern,
+            start: MillisDurationU32::from_ticks(0),
+        }
+    }
+
+    pub fn release(self) -> L {
+        self.led
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Replaces the pattern, restarting it from `now`
+    pub fn set_pattern(&mut self, pattern: Pattern, now: MillisDurationU32) {
+        self.pattern = pattern;
+        self.start = now;
+    }
+
+    /// Updates the LED for the time `now`, returns how long until the next change
+    ///
+    /// `now` is measured from any epoch and may wrap around
+    pub fn poll(&mut self, now: MillisDurationU32) -> MillisDurationU32 {
+        let elapsed = now.ticks().wrapping_sub(self.start.ticks());
+        let (level, next) = self.pattern.level(elapsed);
+        self.led.set_level(level);
+        MillisDurationU32::from_ticks(next)
+    }
+
+    /// Plays the pattern forever, timed by `delay`
+    pub async fn run(&mut self, delay: &mut impl DelayNs) -> ! {
+        let mut now = self.start;
+        loop {
+            let next = self.poll(now);
+            delay.delay_ms(next.ticks()).await;
+            now = MillisDurationU32::from_ticks(now.ticks().wrapping_add(next.ticks()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh1::digital::{Mock, State, Transaction};
+
+    #[test]
+    fn active_low() {
+        let mut led = Led::new(Mock::new(&[
+            Transaction::set(State::Low),
+            Transaction::set(State::High),
+            Transaction::get_state(State::Low),
+            Transaction::toggle(),
+        ]));
+        led.on();
+        led.off();
+        assert!(led.is_on());
+        led.toggle();
+        led.release().done();
+    }
+
+    /// Checks the LED is on for the first `on_ticks` of a PWM period at `brightness`
+    fn assert_pwm(brightness: u8, on_ticks: usize) {
+        let transactions: Vec<Transaction> = (0..PWM_STEPS as usize)
+            .map(|tick| {
+                Transaction::set(if tick < on_ticks {
+                    State::Low
+                } else {
+                    State::High
+                })
+            })
+            .collect();
+        let mut led = PwmLed::new(Led::new(Mock::new(&transactions)));
+        led.set_brightness(brightness);
+        for _ in 0..PWM_STEPS {
+            led.tick();
+        }
+        led.release().release().done();
+    }
+
+    #[test]
+    fn pwm_duty() {
+        assert_pwm(0, 0);
+        assert_pwm(1, 0);
+        assert_pwm(2, 1);
+        assert_pwm(128, 32);
+        assert_pwm(254, 64);
+        assert_pwm(255, 64);
+    }
+
+    /// Levels and durations of the steps of one period of `pattern`, walked with the
+    /// returned time until the next change
+    fn steps(pattern: Pattern, period_ms: u32) -> Vec<(u8, u32)> {
+        let mut steps: Vec<(u8, u32)> = Vec::new();
+        let mut t = 0;
+        while t < period_ms {
+            let (level, next) = pattern.level(t);
+            assert!(next > 0);
+            match steps.last_mut() {
+                Some((last, duration)) if *last == level => *duration += next,
+                _ => steps.push((level, next)),
+            }
+            t += next;
+        }
+        assert_eq!(t, period_ms);
+        steps
+    }
+
+    #[test]
+    fn sos() {
+        let units: Vec<(u8, u32)> = steps(Pattern::Sos, 34 * SOS_UNIT_MS)
+            .into_iter()
+            .map(|(level, duration)| (level, duration / SOS_UNIT_MS))
+            .collect();
+        // Dots of one unit, dashes of three, 7 units between words
+        assert_eq!(
+            units,
+            [
+                (255, 1),
+                (0, 1),
+                (255, 1),
+                (0, 1),
+                (255, 1),
+                (0, 3),
+                (255, 3),
+                (0, 1),
+                (255, 3),
+                (0, 1),
+                (255, 3),
+                (0, 3),
+                (255, 1),
+                (0, 1),
+                (255, 1),
+                (0, 1),
+                (255, 1),
+                (0, 7),
+            ]
+        );
+        assert_eq!(Pattern::Sos.level(34 * SOS_UNIT_MS), Pattern::Sos.level(0));
+    }
+
+    #[test]
+    fn blink() {
+        assert_eq!(steps(Pattern::Blink(2), 500), [(255, 250), (0, 250)]);
+        assert_eq!(Pattern::Blink(2).level(249), (255, 1));
+        assert_eq!(Pattern::Blink(2).level(750), (0, 250));
+        // Clamped to 1 ms steps
+        assert_eq!(steps(Pattern::Blink(255), 2), [(255, 1), (0, 1)]);
+        assert_eq!(Pattern::Blink(0).level(123), (0, IDLE_MS));
+    }
+
+    #[test]
+    fn heartbeat_and_pulses() {
+        assert_eq!(steps(Pattern::Heartbeat, 1000), HEARTBEAT);
+        let pulses = Pattern::Pulses {
+            count: 2,
+            pause_ms: 500,
+        };
+        assert_eq!(
+            steps(pulses, 1100),
+            [(255, 150), (0, 150), (255, 150), (0, 650)]
+        );
+        assert_eq!(pulses.level(1100), pulses.level(0));
+    }
+
+    #[test]
+    fn breathe() {
+        let fade = Pattern::Fade { period_ms: 1000 };
+        assert_eq!(fade.level(0), (0, FADE_STEP_MS));
+        assert_eq!(fade.level(250), (127, FADE_STEP_MS));
+        assert_eq!(fade.level(500), (255, FADE_STEP_MS));
+        assert_eq!(fade.level(750), (127, FADE_STEP_MS));
+        // Last step shortened to end on the period
+        assert_eq!(fade.level(995), (2, 5));
+        assert_eq!(fade.level(1000), fade.level(0));
+        // Ramps up then down
+        let levels: Vec<u8> = (0..1000).step_by(10).map(|t| fade.level(t).0).collect();
+        assert!(levels[..50].windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(levels[50..].windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(Pattern::Fade { period_ms: 0 }.level(10), (0, IDLE_MS));
+    }
+
+    #[test]
+    fn elapsed_overflow() {
+        for pattern in [
+            Pattern::Sos,
+            Pattern::Blink(3),
+            Pattern::Heartbeat,
+            Pattern::Fade { period_ms: 999 },
+        ] {
+            let (_, next) = pattern.level(u32::MAX);
+            assert!(next > 0);
+        }
+    }
+
+    #[derive(Default)]
+    struct Level(u8);
+
+    impl Dimmable for Level {
+        fn set_level(&mut self, level: u8) {
+            self.0 = level;
+        }
+    }
+
+    #[test]
+    fn poll_across_clock_wrap() {
+        let mut sequencer = Sequencer::new(Level::default(), Pattern::Blink(2));
+        sequencer.set_pattern(
+            Pattern::Blink(2),
+            MillisDurationU32::from_ticks(u32::MAX - 99),
+        );
+        // 200 ms after the start, the clock having wrapped
+        let next = sequencer.poll(MillisDurationU32::from_ticks(100));
+        assert_eq!(next.ticks(), 50);
+        assert_eq!(sequencer.release().0, 255);
+
+        let mut sequencer = Sequencer::new(Level::default(), Pattern::Blink(2));
+        sequencer.set_pattern(
+            Pattern::Blink(2),
+            MillisDurationU32::from_ticks(u32::MAX - 99),
+        );
+        let next = sequencer.poll(MillisDurationU32::from_ticks(200));
+        assert_eq!(next.ticks(), 200);
+        assert_eq!(sequencer.release().0, 0);
+    }
 }
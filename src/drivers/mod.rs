@@ -1,2 +1,3 @@
 pub mod led;
 pub mod pmic;
+pub mod qspi_flash;
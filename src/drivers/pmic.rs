@@ -1,10 +1,105 @@
-//! Pmic WIP
+//! Pmic
+//!
+//! Driver for the NXP MC34PF1550 power management IC
+//!
+//! The PF1550 supplies the SDRAM, the Ethernet PHY and the WiFi module of the board
+//! through three buck converters (SW1-SW3), three linear regulators (LDO1-LDO3)
+//! and the DDR reference voltage (VREFDDR).
+//!
+//! [`Pmic`] works on `embedded-hal` blocking I2C buses, [`AsyncPmic`] on
+//! `embedded-hal-async` ones.
+//!
 
 const PMIC_ADDR: u8 = 0x08;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Reg {
     DeviceId = 0x00,
+    OtpFlavor = 0x01,
+    SiliconRev = 0x02,
+    IntCategory = 0x06,
+    SwIntStat0 = 0x08,
+    SwIntMask0 = 0x09,
+    SwIntSense0 = 0x0A,
+    SwIntStat1 = 0x0B,
+    SwIntMask1 = 0x0C,
+    SwIntSense1 = 0x0D,
+    SwIntStat2 = 0x0E,
+    SwIntMask2 = 0x0F,
+    SwIntSense2 = 0x10,
+    LdoIntStat0 = 0x18,
+    LdoIntMask0 = 0x19,
+    LdoIntSense0 = 0x1A,
+    TempIntStat0 = 0x20,
+    TempIntMask0 = 0x21,
+    TempIntSense0 = 0x22,
+    OnkeyIntStat0 = 0x24,
+    OnkeyIntMask0 = 0x25,
+    OnkeyIntSense0 = 0x26,
+    MiscIntStat0 = 0x28,
+    MiscIntMask0 = 0x29,
+    MiscIntSense0 = 0x2A,
+    CoincellControl = 0x30,
+    Sw1Volt = 0x32,
+    Sw1StbyVolt = 0x33,
+    Sw1SlpVolt = 0x34,
+    Sw1Ctrl = 0x35,
+    Sw1Ctrl1 = 0x36,
+    Sw2Volt = 0x38,
+    Sw2StbyVolt = 0x39,
+    Sw2SlpVolt = 0x3A,
+    Sw2Ctrl = 0x3B,
+    Sw2Ctrl1 = 0x3C,
+    Sw3Volt = 0x3E,
+    Sw3StbyVolt = 0x3F,
+    Sw3SlpVolt = 0x40,
+    Sw3Ctrl = 0x41,
+    Sw3Ctrl1 = 0x42,
+    VsnvsCtrl = 0x48,
+    VrefDdrCtrl = 0x4A,
+    Ldo1Volt = 0x4C,
+    Ldo1Ctrl = 0x4D,
+    Ldo2Volt = 0x4F,
+    Ldo2Ctrl = 0x50,
+    Ldo3Volt = 0x52,
+    Ldo3Ctrl = 0x53,
+    PwrCtrl0 = 0x58,
+    PwrCtrl1 = 0x59,
+    PwrCtrl2 = 0x5A,
+    PwrCtrl3 = 0x5B,
+    Sw1PwrdnSeq = 0x5F,
+    Sw2PwrdnSeq = 0x60,
+    Sw3PwrdnSeq = 0x61,
+    Ldo1PwrdnSeq = 0x62,
+    Ldo2PwrdnSeq = 0x63,
+    Ldo3PwrdnSeq = 0x64,
+    VrefDdrPwrdnSeq = 0x65,
+    StateInfo = 0x67,
+    I2cAddr = 0x68,
+    ChgInt = 0x80,
+    ChgIntMask = 0x82,
+    ChgIntOk = 0x84,
+    VbusSns = 0x86,
+    ChgSns = 0x87,
+    BattSns = 0x88,
+    ChgOper = 0x89,
+    ChgTmr = 0x8A,
+    ChgEocCnfg = 0x8D,
+    ChgCurrCnfg = 0x8E,
+    BattReg = 0x8F,
+    BatfetCnfg = 0x91,
+    ThmRegCnfg = 0x92,
+    VbusInlimCnfg = 0x94,
+    VbusLinDpm = 0x95,
+    UsbPhyLdoCnfg = 0x96,
+    DbncDelayTime = 0x98,
+    ChgIntCnfg = 0x99,
+    ThmAdjSetting = 0x9A,
+    Vbus2SysCnfg = 0x9B,
+    LedPwm = 0x9C,
+    FaultBatfetCnfg = 0x9D,
+    LedCnfg = 0x9E,
+    ChgrKey2 = 0x9F,
 }
 
 impl Reg {
@@ -13,27 +108,1093 @@ impl Reg {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum Error {
-    I2cError,
+/// MC34PF1550 device ID
+pub const DEVICE_ID: u8 = 0x7C;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Error<E> {
+    /// Error reported by the I2C bus
+    I2c(E),
+    /// Device ID register does not hold [`DEVICE_ID`]
+    UnexpectedDeviceId(u8),
+    /// Voltage not supported by the rail or the charger
+    VoltageOutOfRange(Millivolts),
+    /// Current not supported by the charger
+    CurrentOutOfRange(Milliamps),
+}
+
+impl<E: embedded_hal_v1::i2c::Error> Error<E> {
+    /// Classification of the bus error, `None` for errors raised by the driver
+    pub fn i2c_kind(&self) -> Option<embedded_hal_v1::i2c::ErrorKind> {
+        match self {
+            Error::I2c(error) => Some(error.kind()),
+            _ => None,
+        }
+    }
+}
+
+/// Voltage in millivolts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Millivolts(pub u16);
+
+/// SW1 and SW2 output voltages, as decoded in fixed (non-DVS) mode which is how the
+/// Portenta OTP configures both converters
+const SW12_VOLTAGES: [u16; 8] = [1100, 1200, 1350, 1500, 1800, 2500, 3000, 3300];
+/// SW3 output voltages, 1.8 V to 3.3 V in 100 mV steps
+const SW3_VOLTAGES: [u16; 16] = [
+    1800, 1900, 2000, 2100, 2200, 2300, 2400, 2500, 2600, 2700, 2800, 2900, 3000, 3100, 3200, 3300,
+];
+/// LDO1 and LDO3 output voltages, 0.75 V to 1.5 V in 50 mV steps then 1.8 V to 3.3 V in 100 mV steps
+const LDO13_VOLTAGES: [u16; 32] = [
+    750, 800, 850, 900, 950, 1000, 1050, 1100, 1150, 1200, 1250, 1300, 1350, 1400, 1450, 1500,
+    1800, 1900, 2000, 2100, 2200, 2300, 2400, 2500, 2600, 2700, 2800, 2900, 3000, 3100, 3200, 3300,
+];
+/// LDO2 output voltages, 1.8 V to 3.3 V in 100 mV steps
+const LDO2_VOLTAGES: [u16; 16] = SW3_VOLTAGES;
+
+/// Buck converters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Buck {
+    Sw1,
+    Sw2,
+    Sw3,
+}
+
+impl Buck {
+    const fn volt_reg(&self) -> Reg {
+        match self {
+            Buck::Sw1 => Reg::Sw1Volt,
+            Buck::Sw2 => Reg::Sw2Volt,
+            Buck::Sw3 => Reg::Sw3Volt,
+        }
+    }
+
+    const fn standby_volt_reg(&self) -> Reg {
+        match self {
+            Buck::Sw1 => Reg::Sw1StbyVolt,
+            Buck::Sw2 => Reg::Sw2StbyVolt,
+            Buck::Sw3 => Reg::Sw3StbyVolt,
+        }
+    }
+
+    const fn voltages(&self) -> &'static [u16] {
+        match self {
+            Buck::Sw1 | Buck::Sw2 => &SW12_VOLTAGES,
+            Buck::Sw3 => &SW3_VOLTAGES,
+        }
+    }
+}
+
+/// Linear regulators
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Ldo {
+    Ldo1,
+    Ldo2,
+    Ldo3,
+}
+
+impl Ldo {
+    const fn volt_reg(&self) -> Reg {
+        match self {
+            Ldo::Ldo1 => Reg::Ldo1Volt,
+            Ldo::Ldo2 => Reg::Ldo2Volt,
+            Ldo::Ldo3 => Reg::Ldo3Volt,
+        }
+    }
+
+    const fn voltages(&self) -> &'static [u16] {
+        match self {
+            Ldo::Ldo1 | Ldo::Ldo3 => &LDO13_VOLTAGES,
+            Ldo::Ldo2 => &LDO2_VOLTAGES,
+        }
+    }
+}
+
+/// Supply rails which can be switched on and off, VREFDDR follows half of the DDR
+/// supply and has no programmable voltage
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Rail {
+    Sw1,
+    Sw2,
+    Sw3,
+    Ldo1,
+    Ldo2,
+    Ldo3,
+    VrefDdr,
+}
+
+impl Rail {
+    const fn ctrl_reg(&self) -> Reg {
+        match self {
+            Rail::Sw1 => Reg::Sw1Ctrl,
+            Rail::Sw2 => Reg::Sw2Ctrl,
+            Rail::Sw3 => Reg::Sw3Ctrl,
+            Rail::Ldo1 => Reg::Ldo1Ctrl,
+            Rail::Ldo2 => Reg::Ldo2Ctrl,
+            Rail::Ldo3 => Reg::Ldo3Ctrl,
+            Rail::VrefDdr => Reg::VrefDdrCtrl,
+        }
+    }
+}
+
+impl From<Buck> for Rail {
+    fn from(buck: Buck) -> Self {
+        match buck {
+            Buck::Sw1 => Rail::Sw1,
+            Buck::Sw2 => Rail::Sw2,
+            Buck::Sw3 => Rail::Sw3,
+        }
+    }
+}
+
+impl From<Ldo> for Rail {
+    fn from(ldo: Ldo) -> Self {
+        match ldo {
+            Ldo::Ldo1 => Rail::Ldo1,
+            Ldo::Ldo2 => Rail::Ldo2,
+            Ldo::Ldo3 => Rail::Ldo3,
+        }
+    }
+}
+
+/// Rail enable in run mode
+const CTRL_EN: u8 = 1 << 0;
+/// Rail enable in standby mode
+const CTRL_STBY_EN: u8 = 1 << 1;
+
+fn decode_voltage(voltages: &[u16], value: u8) -> Millivolts {
+    // Tables are sized to a power of two, masking keeps reserved bits out of the lookup
+    Millivolts(voltages[usize::from(value) & (voltages.len() - 1)])
+}
+
+fn encode_voltage(voltages: &[u16], voltage: Millivolts) -> Option<u8> {
+    voltages
+        .iter()
+        .position(|&mv| mv == voltage.0)
+        .map(|code| code as u8)
+}
+
+/// Current in milliamps
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Milliamps(pub u16);
+
+/// Fast charge current, 100 mA to 1 A in 50 mA steps
+const CHARGE_CURRENT_MIN: u16 = 100;
+const CHARGE_CURRENT_MAX: u16 = 1000;
+const CHARGE_CURRENT_STEP: u16 = 50;
+const CHG_CURR_CNFG_CHGCC: u8 = 0x1F;
+/// Charge termination voltage, 3.5 V to 4.44 V in 20 mV steps
+const CHARGE_VOLTAGE_MIN: u16 = 3500;
+const CHARGE_VOLTAGE_MAX: u16 = 4440;
+const CHARGE_VOLTAGE_STEP: u16 = 20;
+const BATT_REG_CHGCV: u8 = 0x3F;
+
+/// Charger operation, charger on keeps the linear path to the system supply on
+const CHG_OPER_MASK: u8 = 0x03;
+const CHG_OPER_CHARGER_OFF: u8 = 0x01;
+const CHG_OPER_CHARGER_ON: u8 = 0x02;
+
+const CHG_SNS_MASK: u8 = 0x0F;
+const BATT_SNS_MASK: u8 = 0x07;
+/// BATT_SNS battery state, 0x05 being a battery overvoltage
+const BATT_SNS_NO_BATTERY: u8 = 0x06;
+const VBUS_SNS_OVLO: u8 = 1 << 4;
+/// VBUS above UVLO and below OVLO
+const VBUS_SNS_VALID: u8 = 1 << 5;
+
+fn encode_step(value: u16, min: u16, max: u16, step: u16) -> Option<u8> {
+    if value < min || value > max || !(value - min).is_multiple_of(step) {
+        return None;
+    }
+    Some(((value - min) / step) as u8)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ChargerFault {
+    /// Charge timer expired
+    Timer,
+    BatteryOvervoltage,
+    ThermalShutdown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ChargerState {
+    Precharge,
+    FastChargeConstantCurrent,
+    FastChargeConstantVoltage,
+    EndOfCharge,
+    Done,
+    Fault(ChargerFault),
+    /// Battery temperature out of range
+    ThermistorSuspend,
+    /// Charger disabled or no valid input
+    Off,
+    /// Charger disabled, system supplied through the linear path
+    LinearOnly,
+    Unknown(u8),
+}
+
+impl ChargerState {
+    pub const fn from_sense(value: u8) -> Self {
+        match value & CHG_SNS_MASK {
+            0x0 => ChargerState::Precharge,
+            0x1 => ChargerState::FastChargeConstantCurrent,
+            0x2 => ChargerState::FastChargeConstantVoltage,
+            0x3 => ChargerState::EndOfCharge,
+            0x4 => ChargerState::Done,
+            0x6 => ChargerState::Fault(ChargerFault::Timer),
+            0x7 => ChargerState::ThermistorSuspend,
+            0x8 => ChargerState::Off,
+            0x9 => ChargerState::Fault(ChargerFault::BatteryOvervoltage),
+            0xA => ChargerState::Fault(ChargerFault::ThermalShutdown),
+            0xC => ChargerState::LinearOnly,
+            other => ChargerState::Unknown(other),
+        }
+    }
+
+    pub const fn is_charging(&self) -> bool {
+        matches!(
+            self,
+            ChargerState::Precharge
+                | ChargerState::FastChargeConstantCurrent
+                | ChargerState::FastChargeConstantVoltage
+                | ChargerState::EndOfCharge
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ChargerStatus {
+    pub state: ChargerState,
+    pub battery_present: bool,
+    pub vbus_present: bool,
+}
+
+/// PMIC interrupt events
+///
+/// Core events map to a single interrupt bit. `VbusOvervoltage` and `ChargerFault`
+/// are decoded from the VBUS and charger interrupts together with their sense
+/// registers, so they share the interrupt bit (and mask) of `VbusChanged` and
+/// `ChargerStateChanged` respectively. The buck events are raised by the bit of the
+/// buck in any of the SW_INT_STAT0 to SW_INT_STAT2 registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Event {
+    Sw1Overcurrent,
+    Sw2Overcurrent,
+    Sw3Overcurrent,
+    Ldo1Fault,
+    Ldo2Fault,
+    Ldo3Fault,
+    /// Die temperature above 110 °C
+    ThermalWarning,
+    /// Die temperature above 125 °C
+    ThermalCritical,
+    PowerButtonPush,
+    /// Power button held for 1 s
+    PowerButtonHold,
+    VbusChanged,
+    VbusOvervoltage,
+    BatteryChanged,
+    ChargerStateChanged,
+    ChargerFault,
+    ThermistorChanged,
+}
+
+impl Event {
+    pub const ALL: [Event; 16] = [
+        Event::Sw1Overcurrent,
+        Event::Sw2Overcurrent,
+        Event::Sw3Overcurrent,
+        Event::Ldo1Fault,
+        Event::Ldo2Fault,
+        Event::Ldo3Fault,
+        Event::ThermalWarning,
+        Event::ThermalCritical,
+        Event::PowerButtonPush,
+        Event::PowerButtonHold,
+        Event::VbusChanged,
+        Event::VbusOvervoltage,
+        Event::BatteryChanged,
+        Event::ChargerStateChanged,
+        Event::ChargerFault,
+        Event::ThermistorChanged,
+    ];
+
+    const fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+
+    /// Interrupt group and bit signalling the event
+    const fn source(&self) -> (IntGroup, u8) {
+        match self {
+            Event::Sw1Overcurrent => (IntGroup::Sw, 1 << 0),
+            Event::Sw2Overcurrent => (IntGroup::Sw, 1 << 1),
+            Event::Sw3Overcurrent => (IntGroup::Sw, 1 << 2),
+            Event::Ldo1Fault => (IntGroup::Ldo, 1 << 0),
+            Event::Ldo2Fault => (IntGroup::Ldo, 1 << 1),
+            Event::Ldo3Fault => (IntGroup::Ldo, 1 << 2),
+            Event::ThermalWarning => (IntGroup::Temp, 1 << 0),
+            Event::ThermalCritical => (IntGroup::Temp, 1 << 1),
+            Event::PowerButtonPush => (IntGroup::Onkey, 1 << 0),
+            Event::PowerButtonHold => (IntGroup::Onkey, 1 << 1),
+            Event::BatteryChanged => (IntGroup::Chg, CHG_INT_BAT),
+            Event::ChargerStateChanged | Event::ChargerFault => (IntGroup::Chg, CHG_INT_CHG),
+            Event::VbusChanged | Event::VbusOvervoltage => (IntGroup::Chg, CHG_INT_VBUS),
+            Event::ThermistorChanged => (IntGroup::Chg, CHG_INT_THM),
+        }
+    }
+}
+
+/// Set of [`Event`]s
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct EventSet(u32);
+
+impl EventSet {
+    pub const fn empty() -> Self {
+        EventSet(0)
+    }
+
+    pub const fn all() -> Self {
+        EventSet((1 << Event::ALL.len()) - 1)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, event: Event) -> bool {
+        self.0 & event.bit() != 0
+    }
+
+    pub fn insert(&mut self, event: Event) {
+        self.0 |= event.bit();
+    }
+
+    pub fn remove(&mut self, event: Event) {
+        self.0 &= !event.bit();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Event> + '_ {
+        Event::ALL
+            .iter()
+            .copied()
+            .filter(|event| self.contains(*event))
+    }
+
+    /// Bits of `group` selected by the events in the set
+    fn group_bits(&self, group: IntGroup) -> u8 {
+        self.iter()
+            .map(|event| event.source())
+            .filter(|(source, _)| *source == group)
+            .fold(0, |bits, (_, bit)| bits | bit)
+    }
+
+    /// Events signalled by `bits` of `group`, composite events excluded
+    fn from_group_bits(group: IntGroup, bits: u8) -> Self {
+        let mut events = EventSet::empty();
+        for event in Event::ALL {
+            let (source, bit) = event.source();
+            if source == group
+                && bits & bit != 0
+                && !matches!(event, Event::VbusOvervoltage | Event::ChargerFault)
+            {
+                events.insert(event);
+            }
+        }
+        events
+    }
+}
+
+impl From<Event> for EventSet {
+    fn from(event: Event) -> Self {
+        EventSet(event.bit())
+    }
+}
+
+impl core::ops::BitOr for EventSet {
+    type Output = EventSet;
+
+    fn bitor(self, rhs: EventSet) -> EventSet {
+        EventSet(self.0 | rhs.0)
+    }
 }
 
+impl core::ops::BitOr<Event> for EventSet {
+    type Output = EventSet;
+
+    fn bitor(self, rhs: Event) -> EventSet {
+        self | EventSet::from(rhs)
+    }
+}
+
+impl core::ops::BitOr for Event {
+    type Output = EventSet;
+
+    fn bitor(self, rhs: Event) -> EventSet {
+        EventSet::from(self) | rhs
+    }
+}
+
+/// Interrupt status, mask and sense registers sharing the same bit layout
+///
+/// The buck interrupts span three sets of registers, SW1 to SW3 on bits 0 to 2 of
+/// each. The MISC interrupts signal no [`Event`] and are only cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IntGroup {
+    Sw,
+    Ldo,
+    Temp,
+    Onkey,
+    Chg,
+    Misc,
+}
+
+impl IntGroup {
+    const ALL: [IntGroup; 6] = [
+        IntGroup::Sw,
+        IntGroup::Ldo,
+        IntGroup::Temp,
+        IntGroup::Onkey,
+        IntGroup::Chg,
+        IntGroup::Misc,
+    ];
+
+    /// INT_CATEGORY bits flagging a pending interrupt in the group, one per buck for
+    /// [`IntGroup::Sw`]
+    const fn category(&self) -> u8 {
+        match self {
+            IntGroup::Chg => 1 << 0,
+            IntGroup::Sw => 0b111 << 1,
+            IntGroup::Ldo => 1 << 4,
+            IntGroup::Onkey => 1 << 5,
+            IntGroup::Temp => 1 << 6,
+            IntGroup::Misc => 1 << 7,
+        }
+    }
+
+    const fn stat_regs(&self) -> &'static [Reg] {
+        match self {
+            IntGroup::Sw => &[Reg::SwIntStat0, Reg::SwIntStat1, Reg::SwIntStat2],
+            IntGroup::Ldo => &[Reg::LdoIntStat0],
+            IntGroup::Temp => &[Reg::TempIntStat0],
+            IntGroup::Onkey => &[Reg::OnkeyIntStat0],
+            IntGroup::Chg => &[Reg::ChgInt],
+            IntGroup::Misc => &[Reg::MiscIntStat0],
+        }
+    }
+
+    const fn mask_regs(&self) -> &'static [Reg] {
+        match self {
+            IntGroup::Sw => &[Reg::SwIntMask0, Reg::SwIntMask1, Reg::SwIntMask2],
+            IntGroup::Ldo => &[Reg::LdoIntMask0],
+            IntGroup::Temp => &[Reg::TempIntMask0],
+            IntGroup::Onkey => &[Reg::OnkeyIntMask0],
+            IntGroup::Chg => &[Reg::ChgIntMask],
+            IntGroup::Misc => &[Reg::MiscIntMask0],
+        }
+    }
+
+    const fn sense_regs(&self) -> &'static [Reg] {
+        match self {
+            IntGroup::Sw => &[Reg::SwIntSense0, Reg::SwIntSense1, Reg::SwIntSense2],
+            IntGroup::Ldo => &[Reg::LdoIntSense0],
+            IntGroup::Temp => &[Reg::TempIntSense0],
+            IntGroup::Onkey => &[Reg::OnkeyIntSense0],
+            IntGroup::Chg => &[Reg::ChgIntOk],
+            IntGroup::Misc => &[Reg::MiscIntSense0],
+        }
+    }
+}
+
+const CHG_INT_BAT: u8 = 1 << 3;
+const CHG_INT_CHG: u8 = 1 << 4;
+const CHG_INT_VBUS: u8 = 1 << 5;
+const CHG_INT_THM: u8 = 1 << 7;
+
+/// Blocking driver
 pub struct Pmic<I2C> {
     i2c: I2C,
 }
 
-impl<I2C> Pmic<I2C> {
-    pub fn new(i2c: I2C) -> Self {
-        Self { i2c }
-    }
+/// Async driver, sharing the API of [`Pmic`] with awaitable methods
+pub struct AsyncPmic<I2C> {
+    i2c: I2C,
 }
 
-impl<I2C: embedded_hal_v0::blocking::i2c::WriteRead> Pmic<I2C> {
-    pub fn device_id(&mut self) -> Result<u8, Error> {
-        let mut data = [0u8];
-        self.i2c
-            .write_read(PMIC_ADDR, &[Reg::DeviceId.as_u8()], &mut data)
-            .map_err(|_| Error::I2cError)?;
-        Ok(data[0])
+/// Driver methods, generated for both the blocking and the async driver
+macro_rules! impl_pmic {
+    ($($async:ident)?; $($await:tt)*) => {
+        pub fn new(i2c: I2C) -> Self {
+            Self { i2c }
+        }
+
+        pub fn release(self) -> I2C {
+            self.i2c
+        }
+
+        pub $($async)? fn device_id(&mut self) -> Result<u8, Error<I2C::Error>> {
+            self.read_reg(Reg::DeviceId)$($await)*
+        }
+
+        /// Reads the device ID and checks it matches the MC34PF1550
+        pub $($async)? fn verify_device_id(&mut self) -> Result<u8, Error<I2C::Error>> {
+            match self.device_id()$($await)*? {
+                DEVICE_ID => Ok(DEVICE_ID),
+                id => Err(Error::UnexpectedDeviceId(id)),
+            }
+        }
+
+        pub $($async)? fn read_reg(&mut self, reg: Reg) -> Result<u8, Error<I2C::Error>> {
+            let mut data = [0u8];
+            self.i2c
+                .write_read(PMIC_ADDR, &[reg.as_u8()], &mut data)
+                $($await)*
+                .map_err(Error::I2c)?;
+            Ok(data[0])
+        }
+
+        pub $($async)? fn write_reg(
+            &mut self,
+            reg: Reg,
+            value: u8,
+        ) -> Result<(), Error<I2C::Error>> {
+            self.i2c
+                .write(PMIC_ADDR, &[reg.as_u8(), value])
+                $($await)*
+                .map_err(Error::I2c)
+        }
+
+        /// Read-modify-write of the bits selected by `mask`
+        pub $($async)? fn modify_reg(
+            &mut self,
+            reg: Reg,
+            mask: u8,
+            value: u8,
+        ) -> Result<(), Error<I2C::Error>> {
+            let current = self.read_reg(reg)$($await)*?;
+            self.write_reg(reg, (current & !mask) | (value & mask))$($await)*
+        }
+
+        pub $($async)? fn buck_voltage(
+            &mut self,
+            buck: Buck,
+        ) -> Result<Millivolts, Error<I2C::Error>> {
+            let value = self.read_reg(buck.volt_reg())$($await)*?;
+            Ok(decode_voltage(buck.voltages(), value))
+        }
+
+        pub $($async)? fn set_buck_voltage(
+            &mut self,
+            buck: Buck,
+            voltage: Millivolts,
+        ) -> Result<(), Error<I2C::Error>> {
+            let code = encode_voltage(buck.voltages(), voltage)
+                .ok_or(Error::VoltageOutOfRange(voltage))?;
+            self.write_reg(buck.volt_reg(), code)$($await)*
+        }
+
+        pub $($async)? fn buck_standby_voltage(
+            &mut self,
+            buck: Buck,
+        ) -> Result<Millivolts, Error<I2C::Error>> {
+            let value = self.read_reg(buck.standby_volt_reg())$($await)*?;
+            Ok(decode_voltage(buck.voltages(), value))
+        }
+
+        pub $($async)? fn set_buck_standby_voltage(
+            &mut self,
+            buck: Buck,
+            voltage: Millivolts,
+        ) -> Result<(), Error<I2C::Error>> {
+            let code = encode_voltage(buck.voltages(), voltage)
+                .ok_or(Error::VoltageOutOfRange(voltage))?;
+            self.write_reg(buck.standby_volt_reg(), code)$($await)*
+        }
+
+        pub $($async)? fn ldo_voltage(
+            &mut self,
+            ldo: Ldo,
+        ) -> Result<Millivolts, Error<I2C::Error>> {
+            let value = self.read_reg(ldo.volt_reg())$($await)*?;
+            Ok(decode_voltage(ldo.voltages(), value))
+        }
+
+        pub $($async)? fn set_ldo_voltage(
+            &mut self,
+            ldo: Ldo,
+            voltage: Millivolts,
+        ) -> Result<(), Error<I2C::Error>> {
+            let code = encode_voltage(ldo.voltages(), voltage)
+                .ok_or(Error::VoltageOutOfRange(voltage))?;
+            self.write_reg(ldo.volt_reg(), code)$($await)*
+        }
+
+        pub $($async)? fn is_enabled(
+            &mut self,
+            rail: impl Into<Rail>,
+        ) -> Result<bool, Error<I2C::Error>> {
+            Ok(self.read_reg(rail.into().ctrl_reg())$($await)*? & CTRL_EN != 0)
+        }
+
+        pub $($async)? fn set_enabled(
+            &mut self,
+            rail: impl Into<Rail>,
+            enabled: bool,
+        ) -> Result<(), Error<I2C::Error>> {
+            let value = if enabled { CTRL_EN } else { 0 };
+            self.modify_reg(rail.into().ctrl_reg(), CTRL_EN, value)$($await)*
+        }
+
+        pub $($async)? fn is_standby_enabled(
+            &mut self,
+            rail: impl Into<Rail>,
+        ) -> Result<bool, Error<I2C::Error>> {
+            Ok(self.read_reg(rail.into().ctrl_reg())$($await)*? & CTRL_STBY_EN != 0)
+        }
+
+        pub $($async)? fn set_standby_enabled(
+            &mut self,
+            rail: impl Into<Rail>,
+            enabled: bool,
+        ) -> Result<(), Error<I2C::Error>> {
+            let value = if enabled { CTRL_STBY_EN } else { 0 };
+            self.modify_reg(rail.into().ctrl_reg(), CTRL_STBY_EN, value)$($await)*
+        }
+
+        pub $($async)? fn charge_current(&mut self) -> Result<Milliamps, Error<I2C::Error>> {
+            let code = u16::from(self.read_reg(Reg::ChgCurrCnfg)$($await)*? & CHG_CURR_CNFG_CHGCC);
+            Ok(Milliamps(
+                (CHARGE_CURRENT_MIN + code * CHARGE_CURRENT_STEP).min(CHARGE_CURRENT_MAX),
+            ))
+        }
+
+        pub $($async)? fn set_charge_current(
+            &mut self,
+            current: Milliamps,
+        ) -> Result<(), Error<I2C::Error>> {
+            let code = encode_step(
+                current.0,
+                CHARGE_CURRENT_MIN,
+                CHARGE_CURRENT_MAX,
+                CHARGE_CURRENT_STEP,
+            )
+            .ok_or(Error::CurrentOutOfRange(current))?;
+            self.modify_reg(Reg::ChgCurrCnfg, CHG_CURR_CNFG_CHGCC, code)$($await)*
+        }
+
+        pub $($async)? fn charge_voltage(&mut self) -> Result<Millivolts, Error<I2C::Error>> {
+            let code = u16::from(self.read_reg(Reg::BattReg)$($await)*? & BATT_REG_CHGCV);
+            Ok(Millivolts(
+                (CHARGE_VOLTAGE_MIN + code * CHARGE_VOLTAGE_STEP).min(CHARGE_VOLTAGE_MAX),
+            ))
+        }
+
+        /// Sets the termination voltage of the constant voltage charge phase
+        pub $($async)? fn set_charge_voltage(
+            &mut self,
+            voltage: Millivolts,
+        ) -> Result<(), Error<I2C::Error>> {
+            let code = encode_step(
+                voltage.0,
+                CHARGE_VOLTAGE_MIN,
+                CHARGE_VOLTAGE_MAX,
+                CHARGE_VOLTAGE_STEP,
+            )
+            .ok_or(Error::VoltageOutOfRange(voltage))?;
+            self.modify_reg(Reg::BattReg, BATT_REG_CHGCV, code)$($await)*
+        }
+
+        pub $($async)? fn is_charging_enabled(&mut self) -> Result<bool, Error<I2C::Error>> {
+            Ok(self.read_reg(Reg::ChgOper)$($await)*? & CHG_OPER_MASK == CHG_OPER_CHARGER_ON)
+        }
+
+        pub $($async)? fn set_charging_enabled(
+            &mut self,
+            enabled: bool,
+        ) -> Result<(), Error<I2C::Error>> {
+            let value = if enabled {
+                CHG_OPER_CHARGER_ON
+            } else {
+                CHG_OPER_CHARGER_OFF
+            };
+            self.modify_reg(Reg::ChgOper, CHG_OPER_MASK, value)$($await)*
+        }
+
+        pub $($async)? fn charger_state(&mut self) -> Result<ChargerState, Error<I2C::Error>> {
+            Ok(ChargerState::from_sense(self.read_reg(Reg::ChgSns)$($await)*?))
+        }
+
+        pub $($async)? fn battery_present(&mut self) -> Result<bool, Error<I2C::Error>> {
+            Ok(self.read_reg(Reg::BattSns)$($await)*? & BATT_SNS_MASK != BATT_SNS_NO_BATTERY)
+        }
+
+        pub $($async)? fn vbus_present(&mut self) -> Result<bool, Error<I2C::Error>> {
+            Ok(self.read_reg(Reg::VbusSns)$($await)*? & VBUS_SNS_VALID != 0)
+        }
+
+        pub $($async)? fn charger_status(&mut self) -> Result<ChargerStatus, Error<I2C::Error>> {
+            Ok(ChargerStatus {
+                state: self.charger_state()$($await)*?,
+                battery_present: self.battery_present()$($await)*?,
+                vbus_present: self.vbus_present()$($await)*?,
+            })
+        }
+
+        /// Masks (`masked = true`) or unmasks the interrupts signalling `events`
+        pub $($async)? fn set_events_masked(
+            &mut self,
+            events: EventSet,
+            masked: bool,
+        ) -> Result<(), Error<I2C::Error>> {
+            for group in IntGroup::ALL {
+                let bits = events.group_bits(group);
+                if bits != 0 {
+                    let value = if masked { bits } else { 0 };
+                    for &reg in group.mask_regs() {
+                        self.modify_reg(reg, bits, value)$($await)*?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Events whose interrupt is currently masked, in every register of its group
+        pub $($async)? fn masked_events(&mut self) -> Result<EventSet, Error<I2C::Error>> {
+            let mut events = EventSet::empty();
+            for group in IntGroup::ALL {
+                if group == IntGroup::Misc {
+                    continue;
+                }
+                let mut bits = 0xFF;
+                for &reg in group.mask_regs() {
+                    bits &= self.read_reg(reg)$($await)*?;
+                }
+                for event in Event::ALL {
+                    let (source, bit) = event.source();
+                    if source == group && bits & bit != 0 {
+                        events.insert(event);
+                    }
+                }
+            }
+            Ok(events)
+        }
+
+        /// Reads and clears the pending events
+        pub $($async)? fn take_events(&mut self) -> Result<EventSet, Error<I2C::Error>> {
+            let category = self.read_reg(Reg::IntCategory)$($await)*?;
+            let mut events = EventSet::empty();
+            for group in IntGroup::ALL {
+                if category & group.category() == 0 {
+                    continue;
+                }
+                for &reg in group.stat_regs() {
+                    let bits = self.read_reg(reg)$($await)*?;
+                    if bits == 0 {
+                        continue;
+                    }
+                    // Status bits are cleared by writing them back
+                    self.write_reg(reg, bits)$($await)*?;
+                    events = events | EventSet::from_group_bits(group, bits);
+                }
+            }
+            self.decode_composite(&mut events)$($await)*?;
+            Ok(events)
+        }
+
+        /// Conditions currently reported by the sense registers, regardless of masking
+        pub $($async)? fn sensed_events(&mut self) -> Result<EventSet, Error<I2C::Error>> {
+            let mut events = EventSet::empty();
+            for group in [IntGroup::Sw, IntGroup::Ldo, IntGroup::Temp, IntGroup::Onkey] {
+                for &reg in group.sense_regs() {
+                    let bits = self.read_reg(reg)$($await)*?;
+                    events = events | EventSet::from_group_bits(group, bits);
+                }
+            }
+            if self.read_reg(Reg::VbusSns)$($await)*? & VBUS_SNS_OVLO != 0 {
+                events.insert(Event::VbusOvervoltage);
+            }
+            if matches!(self.charger_state()$($await)*?, ChargerState::Fault(_)) {
+                events.insert(Event::ChargerFault);
+            }
+            Ok(events)
+        }
+
+        $($async)? fn decode_composite(
+            &mut self,
+            events: &mut EventSet,
+        ) -> Result<(), Error<I2C::Error>> {
+            if events.contains(Event::VbusChanged)
+                && self.read_reg(Reg::VbusSns)$($await)*? & VBUS_SNS_OVLO != 0
+            {
+                events.insert(Event::VbusOvervoltage);
+            }
+            if events.contains(Event::ChargerStateChanged)
+                && matches!(self.charger_state()$($await)*?, ChargerState::Fault(_))
+            {
+                events.insert(Event::ChargerFault);
+            }
+            Ok(())
+        }
+    };
+}
+
+impl<I2C: embedded_hal_v1::i2c::I2c> Pmic<I2C> {
+    impl_pmic!(;);
+}
+
+impl<I2C: embedded_hal_async::i2c::I2c> AsyncPmic<I2C> {
+    impl_pmic!(async; .await);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    fn read(reg: Reg, value: u8) -> Transaction {
+        Transaction::write_read(PMIC_ADDR, vec![reg.as_u8()], vec![value])
+    }
+
+    fn write(reg: Reg, value: u8) -> Transaction {
+        Transaction::write(PMIC_ADDR, vec![reg.as_u8(), value])
+    }
+
+    fn pmic(transactions: &[Transaction]) -> Pmic<Mock> {
+        Pmic::new(Mock::new(transactions))
+    }
+
+    #[test]
+    fn verify_device_id() {
+        let mut pmic = pmic(&[read(Reg::DeviceId, DEVICE_ID), read(Reg::DeviceId, 0x42)]);
+        assert_eq!(pmic.verify_device_id(), Ok(DEVICE_ID));
+        assert_eq!(
+            pmic.verify_device_id(),
+            Err(Error::UnexpectedDeviceId(0x42))
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn bus_error() {
+        let mut pmic = pmic(&[
+            read(Reg::DeviceId, 0).with_error(embedded_hal_v1::i2c::ErrorKind::ArbitrationLoss)
+        ]);
+        let error = pmic.device_id().unwrap_err();
+        assert_eq!(
+            error.i2c_kind(),
+            Some(embedded_hal_v1::i2c::ErrorKind::ArbitrationLoss)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn buck_voltage() {
+        let mut pmic = pmic(&[
+            read(Reg::Sw1Volt, 0x04),
+            write(Reg::Sw3Volt, 0x0F),
+            read(Reg::Ldo1Volt, 0xF0),
+        ]);
+        assert_eq!(pmic.buck_voltage(Buck::Sw1), Ok(Millivolts(1800)));
+        assert_eq!(pmic.set_buck_voltage(Buck::Sw3, Millivolts(3300)), Ok(()));
+        // Reserved bits ignored
+        assert_eq!(pmic.ldo_voltage(Ldo::Ldo1), Ok(Millivolts(1800)));
+        assert_eq!(
+            pmic.set_buck_voltage(Buck::Sw2, Millivolts(1000)),
+            Err(Error::VoltageOutOfRange(Millivolts(1000)))
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn set_enabled_keeps_other_bits() {
+        let mut pmic = pmic(&[
+            read(Reg::Ldo2Ctrl, 0b1010),
+            write(Reg::Ldo2Ctrl, 0b1011),
+            read(Reg::Sw2Ctrl, 0b0011),
+            write(Reg::Sw2Ctrl, 0b0001),
+        ]);
+        assert_eq!(pmic.set_enabled(Ldo::Ldo2, true), Ok(()));
+        assert_eq!(pmic.set_standby_enabled(Buck::Sw2, false), Ok(()));
+        pmic.release().done();
+    }
+
+    #[test]
+    fn charge_current() {
+        let mut pmic = pmic(&[read(Reg::ChgCurrCnfg, 0xE0), write(Reg::ChgCurrCnfg, 0xE2)]);
+        assert_eq!(pmic.set_charge_current(Milliamps(200)), Ok(()));
+        assert_eq!(
+            pmic.set_charge_current(Milliamps(225)),
+            Err(Error::CurrentOutOfRange(Milliamps(225)))
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn take_events() {
+        let mut pmic = pmic(&[
+            // Charger and temperature interrupts pending
+            read(Reg::IntCategory, 0x41),
+            read(Reg::TempIntStat0, 0x01),
+            write(Reg::TempIntStat0, 0x01),
+            read(Reg::ChgInt, CHG_INT_VBUS | CHG_INT_CHG),
+            write(Reg::ChgInt, CHG_INT_VBUS | CHG_INT_CHG),
+            // VBUS above OVLO, charge timer fault
+            read(Reg::VbusSns, 0x10),
+            read(Reg::ChgSns, 0x06),
+        ]);
+        assert_eq!(
+            pmic.take_events(),
+            Ok(Event::ThermalWarning
+                | Event::VbusChanged
+                | Event::VbusOvervoltage
+                | Event::ChargerStateChanged
+                | Event::ChargerFault)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn take_buck_and_misc_events() {
+        let mut pmic = pmic(&[
+            // SW2, SW3 and MISC interrupts pending
+            read(Reg::IntCategory, 0x8C),
+            read(Reg::SwIntStat0, 0x00),
+            read(Reg::SwIntStat1, 0x02),
+            write(Reg::SwIntStat1, 0x02),
+            read(Reg::SwIntStat2, 0x04),
+            write(Reg::SwIntStat2, 0x04),
+            read(Reg::MiscIntStat0, 0x10),
+            write(Reg::MiscIntStat0, 0x10),
+        ]);
+        assert_eq!(
+            pmic.take_events(),
+            Ok(Event::Sw2Overcurrent | Event::Sw3Overcurrent)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn take_events_without_faults() {
+        let mut pmic = pmic(&[
+            // Buck and charger interrupts pending, nothing left in the buck registers
+            read(Reg::IntCategory, 0x03),
+            read(Reg::SwIntStat0, 0x00),
+            read(Reg::SwIntStat1, 0x00),
+            read(Reg::SwIntStat2, 0x00),
+            read(Reg::ChgInt, CHG_INT_VBUS | CHG_INT_CHG),
+            write(Reg::ChgInt, CHG_INT_VBUS | CHG_INT_CHG),
+            // Valid VBUS, fast charge
+            read(Reg::VbusSns, 0x20),
+            read(Reg::ChgSns, 0x01),
+        ]);
+        assert_eq!(
+            pmic.take_events(),
+            Ok(Event::VbusChanged | Event::ChargerStateChanged)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn take_no_events() {
+        let mut pmic = pmic(&[read(Reg::IntCategory, 0x00)]);
+        assert_eq!(pmic.take_events(), Ok(EventSet::empty()));
+        pmic.release().done();
+    }
+
+    #[test]
+    fn sensed_events() {
+        let mut pmic = pmic(&[
+            read(Reg::SwIntSense0, 0x00),
+            read(Reg::SwIntSense1, 0x04),
+            read(Reg::SwIntSense2, 0x00),
+            read(Reg::LdoIntSense0, 0x00),
+            read(Reg::TempIntSense0, 0x00),
+            read(Reg::OnkeyIntSense0, 0x02),
+            read(Reg::VbusSns, 0x10),
+            read(Reg::ChgSns, 0x0A),
+        ]);
+        assert_eq!(
+            pmic.sensed_events(),
+            Ok(Event::Sw3Overcurrent
+                | Event::PowerButtonHold
+                | Event::VbusOvervoltage
+                | Event::ChargerFault)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn mask_events() {
+        let mut pmic = pmic(&[
+            read(Reg::SwIntMask0, 0x01),
+            write(Reg::SwIntMask0, 0x03),
+            read(Reg::SwIntMask1, 0x00),
+            write(Reg::SwIntMask1, 0x02),
+            read(Reg::SwIntMask2, 0x02),
+            write(Reg::SwIntMask2, 0x02),
+            read(Reg::TempIntMask0, 0x00),
+            write(Reg::TempIntMask0, 0x01),
+            // VbusOvervoltage shares the VBUS interrupt
+            read(Reg::ChgIntMask, 0xFF),
+            write(Reg::ChgIntMask, 0xDF),
+        ]);
+        assert_eq!(
+            pmic.set_events_masked(Event::Sw2Overcurrent | Event::ThermalWarning, true),
+            Ok(())
+        );
+        assert_eq!(
+            pmic.set_events_masked(Event::VbusOvervoltage.into(), false),
+            Ok(())
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn masked_events() {
+        let mut pmic = pmic(&[
+            // SW1 masked in every buck register, SW2 in one only
+            read(Reg::SwIntMask0, 0x03),
+            read(Reg::SwIntMask1, 0x01),
+            read(Reg::SwIntMask2, 0x01),
+            read(Reg::LdoIntMask0, 0x00),
+            read(Reg::TempIntMask0, 0x02),
+            read(Reg::OnkeyIntMask0, 0x00),
+            read(Reg::ChgIntMask, CHG_INT_BAT),
+        ]);
+        assert_eq!(
+            pmic.masked_events(),
+            Ok(Event::Sw1Overcurrent | Event::ThermalCritical | Event::BatteryChanged)
+        );
+        pmic.release().done();
+    }
+
+    #[test]
+    fn charger_status() {
+        let mut pmic = pmic(&[
+            read(Reg::ChgSns, 0x01),
+            read(Reg::BattSns, 0x06),
+            read(Reg::VbusSns, 0x20),
+            read(Reg::ChgSns, 0x09),
+            read(Reg::BattSns, 0x05),
+            read(Reg::VbusSns, 0x10),
+        ]);
+        assert_eq!(
+            pmic.charger_status(),
+            Ok(ChargerStatus {
+                state: ChargerState::FastChargeConstantCurrent,
+                battery_present: false,
+                vbus_present: true,
+            })
+        );
+        // Battery overvoltage with VBUS above OVLO
+        assert_eq!(
+            pmic.charger_status(),
+            Ok(ChargerStatus {
+                state: ChargerState::Fault(ChargerFault::BatteryOvervoltage),
+                battery_present: true,
+                vbus_present: false,
+            })
+        );
+        pmic.release().done();
     }
 }
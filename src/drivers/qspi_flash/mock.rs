@@ -0,0 +1,208 @@
+//! mock
+//!
+//! Flash emulated in memory, for host tests of the driver and of code using it
+//!
+
+use crate::drivers::qspi_flash::{
+    protected_range, Backend, Command, Instruction, Lines, BLOCK_32K_SIZE, BLOCK_SIZE, CAPACITY,
+    JEDEC_ID, PAGE_SIZE, SECTOR_SIZE,
+};
+
+const WEL: u8 = 1 << 1;
+const BP_MASK: u8 = 0b1111 << 2;
+const QE: u8 = 1 << 6;
+const P_FAIL: u8 = 1 << 5;
+const E_FAIL: u8 = 1 << 6;
+
+/// Error of [`MockBackend`], for commands the flash would not accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum MockError {
+    UnknownInstruction(u8),
+    /// Quad command while the QE bit is clear
+    QuadDisabled,
+    MissingAddress,
+}
+
+/// MX25L12833F emulated over `memory`, addresses wrapping around its length
+///
+/// Programs and erases complete at once, failing like the flash on protected areas
+/// or without a write enable.
+pub struct MockBackend<'a> {
+    memory: &'a mut [u8],
+    status: u8,
+    config: u8,
+    security: u8,
+    /// Commands sent by instruction, in the order of [`Instruction::ALL`]
+    sent: [usize; Instruction::ALL.len()],
+    /// Commands sent, for tests to check
+    pub commands: usize,
+    /// Programs and erases fail, setting P_FAIL or E_FAIL like a worn out flash
+    pub fail_writes: bool,
+}
+
+impl<'a> MockBackend<'a> {
+    /// Flash content, usually erased to 0xFF and [`CAPACITY`] long
+    pub fn new(memory: &'a mut [u8]) -> Self {
+        Self {
+            memory,
+            status: 0,
+            config: 0,
+            security: 0,
+            sent: [0; Instruction::ALL.len()],
+            commands: 0,
+            fail_writes: false,
+        }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.memory
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// Number of `instruction` commands sent
+    pub fn sent(&self, instruction: Instruction) -> usize {
+        Instruction::ALL
+            .iter()
+            .position(|known| *known == instruction)
+            .map_or(0, |index| self.sent[index])
+    }
+
+    fn count(&mut self, command: &Command) {
+        self.commands += 1;
+        if let Some(index) = Instruction::ALL
+            .iter()
+            .position(|known| known.as_u8() == command.instruction)
+        {
+            self.sent[index] += 1;
+        }
+    }
+
+    fn check_lines(&self, command: &Command) -> Result<(), MockError> {
+        let quad = command.address_lines == Lines::Quad || command.data_lines == Lines::Quad;
+        if quad && self.status & QE == 0 {
+            return Err(MockError::QuadDisabled);
+        }
+        Ok(())
+    }
+
+    fn address(&self, command: &Command) -> Result<usize, MockError> {
+        let address = command.address.ok_or(MockError::MissingAddress)?;
+        Ok(address as usize % self.memory.len())
+    }
+
+    fn is_protected(&self, address: usize, len: usize) -> bool {
+        let protected = protected_range((self.status & BP_MASK) >> 2);
+        address + len > protected.start as usize
+    }
+
+    /// Takes the write enable latch, clearing the failure flags of a new program or
+    /// erase
+    fn take_write_enable(&mut self) -> bool {
+        let enabled = self.status & WEL != 0;
+        self.status &= !WEL;
+        enabled
+    }
+
+    fn program(&mut self, command: &Command, data: &[u8]) -> Result<(), MockError> {
+        let address = self.address(command)?;
+        if !self.take_write_enable() {
+            return Ok(());
+        }
+        self.security &= !(P_FAIL | E_FAIL);
+        if self.fail_writes || self.is_protected(address, data.len()) {
+            self.security |= P_FAIL;
+            return Ok(());
+        }
+        // Wraps within the page, programming only clears bits
+        let page = address - address % PAGE_SIZE as usize;
+        for (index, byte) in data.iter().enumerate() {
+            let offset = (address + index - page) % PAGE_SIZE as usize;
+            self.memory[page + offset] &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, command: &Command, size: usize) -> Result<(), MockError> {
+        let address = self.address(command)?;
+        let start = address - address % size;
+        self.erase_range(start, size);
+        Ok(())
+    }
+
+    fn erase_range(&mut self, start: usize, size: usize) {
+        if !self.take_write_enable() {
+            return;
+        }
+        self.security &= !(P_FAIL | E_FAIL);
+        if self.fail_writes || self.is_protected(start, size) {
+            self.security |= E_FAIL;
+            return;
+        }
+        let end = (start + size).min(self.memory.len());
+        self.memory[start..end].fill(0xFF);
+    }
+}
+
+impl Backend for MockBackend<'_> {
+    type Error = MockError;
+
+    fn read(&mut self, command: Command, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.count(&command);
+        self.check_lines(&command)?;
+        match Instruction::from_u8(command.instruction) {
+            Some(Instruction::ReadId) => {
+                let id = [
+                    JEDEC_ID.manufacturer,
+                    JEDEC_ID.memory_type,
+                    JEDEC_ID.capacity,
+                ];
+                for (byte, id) in buffer.iter_mut().zip(id.iter().cycle()) {
+                    *byte = *id;
+                }
+            }
+            Some(Instruction::ReadStatus) => buffer.fill(self.status),
+            Some(Instruction::ReadConfig) => buffer.fill(self.config),
+            Some(Instruction::ReadSecurity) => buffer.fill(self.security),
+            Some(Instruction::Read | Instruction::QuadIoRead) => {
+                let address = self.address(&command)?;
+                for (index, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.memory[(address + index) % self.memory.len()];
+                }
+            }
+            _ => return Err(MockError::UnknownInstruction(command.instruction)),
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, command: Command, data: &[u8]) -> Result<(), Self::Error> {
+        self.count(&command);
+        self.check_lines(&command)?;
+        match Instruction::from_u8(command.instruction) {
+            Some(Instruction::WriteEnable) => self.status |= WEL,
+            Some(Instruction::WriteDisable) => self.status &= !WEL,
+            Some(Instruction::WriteStatus) => {
+                if self.take_write_enable() {
+                    if let Some(status) = data.first() {
+                        self.status = status & (BP_MASK | QE);
+                    }
+                    if let Some(config) = data.get(1) {
+                        self.config = *config;
+                    }
+                }
+            }
+            Some(Instruction::PageProgram | Instruction::QuadPageProgram) => {
+                self.program(&command, data)?
+            }
+            Some(Instruction::SectorErase) => self.erase(&command, SECTOR_SIZE as usize)?,
+            Some(Instruction::Block32KErase) => self.erase(&command, BLOCK_32K_SIZE as usize)?,
+            Some(Instruction::BlockErase) => self.erase(&command, BLOCK_SIZE as usize)?,
+            Some(Instruction::ChipErase) => self.erase_range(0, CAPACITY as usize),
+            Some(Instruction::ResetEnable | Instruction::Reset) => {}
+            _ => return Err(MockError::UnknownInstruction(command.instruction)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,625 @@
+//! QSPI flash
+//!
+//! Driver for the Macronix MX25L12833F 16M NOR flash
+//!
+//! Commands go through a [`Backend`], the QUADSPI peripheral on the board, or
+//! [`mock::MockBackend`] emulating the flash in memory for host tests. Reads and
+//! page programs use the four data lines, which [`QspiFlash::init`] enables.
+//!
+//! [`QspiFlash`] implements the `embedded-storage` NOR flash traits.
+//!
+
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+pub mod mock;
+
+pub const CAPACITY: u32 = 16 * 1024 * 1024;
+pub const PAGE_SIZE: u32 = 256;
+pub const SECTOR_SIZE: u32 = 4 * 1024;
+pub const BLOCK_32K_SIZE: u32 = 32 * 1024;
+pub const BLOCK_SIZE: u32 = 64 * 1024;
+
+/// MX25L12833F JEDEC ID
+pub const JEDEC_ID: JedecId = JedecId {
+    manufacturer: 0xC2,
+    memory_type: 0x20,
+    capacity: 0x18,
+};
+
+/// Dummy cycles of [`Instruction::QuadIoRead`], with the default configuration
+pub const QUAD_IO_READ_DUMMY_CYCLES: u8 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Instruction {
+    WriteEnable = 0x06,
+    WriteDisable = 0x04,
+    ReadId = 0x9F,
+    ReadStatus = 0x05,
+    ReadConfig = 0x15,
+    WriteStatus = 0x01,
+    ReadSecurity = 0x2B,
+    Read = 0x03,
+    /// 4READ, address and data on four lines
+    QuadIoRead = 0xEB,
+    PageProgram = 0x02,
+    /// 4PP, address and data on four lines
+    QuadPageProgram = 0x38,
+    SectorErase = 0x20,
+    Block32KErase = 0x52,
+    BlockErase = 0xD8,
+    ChipErase = 0x60,
+    ResetEnable = 0x66,
+    Reset = 0x99,
+}
+
+impl Instruction {
+    pub const ALL: [Self; 17] = [
+        Self::WriteEnable,
+        Self::WriteDisable,
+        Self::ReadId,
+        Self::ReadStatus,
+        Self::ReadConfig,
+        Self::WriteStatus,
+        Self::ReadSecurity,
+        Self::Read,
+        Self::QuadIoRead,
+        Self::PageProgram,
+        Self::QuadPageProgram,
+        Self::SectorErase,
+        Self::Block32KErase,
+        Self::BlockErase,
+        Self::ChipErase,
+        Self::ResetEnable,
+        Self::Reset,
+    ];
+
+    pub const fn as_u8(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_u8(instruction: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.as_u8() == instruction)
+    }
+}
+
+/// Lines used by a phase of a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Lines {
+    Single,
+    Quad,
+}
+
+/// Command sent by a [`Backend`], the instruction always on a single line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Command {
+    pub instruction: u8,
+    /// 24-bit address
+    pub address: Option<u32>,
+    pub address_lines: Lines,
+    pub dummy_cycles: u8,
+    pub data_lines: Lines,
+}
+
+impl Command {
+    /// Instruction alone, or followed by data on a single line
+    pub const fn new(instruction: Instruction) -> Self {
+        Self {
+            instruction: instruction.as_u8(),
+            address: None,
+            address_lines: Lines::Single,
+            dummy_cycles: 0,
+            data_lines: Lines::Single,
+        }
+    }
+
+    pub const fn address(mut self, address: u32, lines: Lines) -> Self {
+        self.address = Some(address);
+        self.address_lines = lines;
+        self
+    }
+
+    pub const fn dummy_cycles(mut self, cycles: u8) -> Self {
+        self.dummy_cycles = cycles;
+        self
+    }
+
+    pub const fn data_lines(mut self, lines: Lines) -> Self {
+        self.data_lines = lines;
+        self
+    }
+}
+
+/// Sends commands to the flash
+pub trait Backend {
+    type Error: core::fmt::Debug;
+
+    /// Sends `command` and reads `buffer.len()` bytes
+    fn read(&mut self, command: Command, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Sends `command` followed by `data`, possibly empty
+    fn write(&mut self, command: Command, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+/// Status register
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Status(pub u8);
+
+impl Status {
+    const WIP: u8 = 1 << 0;
+    const WEL: u8 = 1 << 1;
+    const BP_SHIFT: u8 = 2;
+    const BP_MASK: u8 = 0b1111 << Self::BP_SHIFT;
+    const QE: u8 = 1 << 6;
+    const SRWD: u8 = 1 << 7;
+
+    pub const fn write_in_progress(&self) -> bool {
+        self.0 & Self::WIP != 0
+    }
+
+    pub const fn write_enabled(&self) -> bool {
+        self.0 & Self::WEL != 0
+    }
+
+    /// BP3-BP0 level, see [`protected_range`]
+    pub const fn block_protection(&self) -> u8 {
+        (self.0 & Self::BP_MASK) >> Self::BP_SHIFT
+    }
+
+    pub const fn with_block_protection(self, level: u8) -> Self {
+        Self(self.0 & !Self::BP_MASK | (level << Self::BP_SHIFT) & Self::BP_MASK)
+    }
+
+    pub const fn quad_enabled(&self) -> bool {
+        self.0 & Self::QE != 0
+    }
+
+    pub const fn with_quad_enabled(self, enable: bool) -> Self {
+        if enable {
+            Self(self.0 | Self::QE)
+        } else {
+            Self(self.0 & !Self::QE)
+        }
+    }
+
+    /// Status register write disable, locking the status register while WP# is low
+    pub const fn status_write_disabled(&self) -> bool {
+        self.0 & Self::SRWD != 0
+    }
+}
+
+/// Security register flags of the last program or erase
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Security(pub u8);
+
+impl Security {
+    const P_FAIL: u8 = 1 << 5;
+    const E_FAIL: u8 = 1 << 6;
+
+    pub const fn program_failed(&self) -> bool {
+        self.0 & Self::P_FAIL != 0
+    }
+
+    pub const fn erase_failed(&self) -> bool {
+        self.0 & Self::E_FAIL != 0
+    }
+}
+
+/// Addresses protected by the block protection `level`, the top blocks of the flash
+/// with the default top/bottom configuration
+pub const fn protected_range(level: u8) -> core::ops::Range<u32> {
+    let blocks = match level {
+        0 => 0,
+        1..=8 => 1 << (level - 1),
+        _ => CAPACITY / BLOCK_SIZE,
+    };
+    CAPACITY - blocks * BLOCK_SIZE..CAPACITY
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Error<E> {
+    /// Error reported by the QSPI backend
+    Qspi(E),
+    /// JEDEC ID is not [`JEDEC_ID`]
+    UnexpectedJedecId(JedecId),
+    /// Access past [`CAPACITY`]
+    OutOfBounds,
+    /// Erase not aligned to its size, or page program crossing a page
+    NotAligned,
+    /// Access to an area locked by the block protection
+    Protected,
+    /// Flash reported the program failed
+    ProgramFailed,
+    /// Flash reported the erase failed
+    EraseFailed,
+}
+
+impl<E: core::fmt::Debug> NorFlashError for Error<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+pub struct QspiFlash<B> {
+    backend: B,
+}
+
+impl<B: Backend> QspiFlash<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    pub fn release(self) -> B {
+        self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Checks the JEDEC ID and enables the four data lines
+    pub fn init(&mut self) -> Result<(), Error<B::Error>> {
+        self.verify_jedec_id()?;
+        self.wait_ready()?;
+        let status = self.status()?;
+        if !status.quad_enabled() {
+            let config = self.config()?;
+            self.write_status(status.with_quad_enabled(true), config)?;
+        }
+        Ok(())
+    }
+
+    pub fn jedec_id(&mut self) -> Result<JedecId, Error<B::Error>> {
+        let mut id = [0; 3];
+        self.backend
+            .read(Command::new(Instruction::ReadId), &mut id)
+            .map_err(Error::Qspi)?;
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    pub fn verify_jedec_id(&mut self) -> Result<JedecId, Error<B::Error>> {
+        match self.jedec_id()? {
+            JEDEC_ID => Ok(JEDEC_ID),
+            id => Err(Error::UnexpectedJedecId(id)),
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status, Error<B::Error>> {
+        self.read_register(Instruction::ReadStatus).map(Status)
+    }
+
+    /// Configuration register, output driver strength and dummy cycles
+    pub fn config(&mut self) -> Result<u8, Error<B::Error>> {
+        self.read_register(Instruction::ReadConfig)
+    }
+
+    pub fn security(&mut self) -> Result<Security, Error<B::Error>> {
+        self.read_register(Instruction::ReadSecurity).map(Security)
+    }
+
+    /// Writes the status and configuration registers, and waits for the write
+    pub fn write_status(&mut self, status: Status, config: u8) -> Result<(), Error<B::Error>> {
+        self.command(Instruction::WriteEnable)?;
+        self.backend
+            .write(Command::new(Instruction::WriteStatus), &[status.0, config])
+            .map_err(Error::Qspi)?;
+        self.wait_ready()
+    }
+
+    /// BP3-BP0 level, see [`protected_range`]
+    pub fn block_protection(&mut self) -> Result<u8, Error<B::Error>> {
+        Ok(self.status()?.block_protection())
+    }
+
+    /// Locks the top blocks given by `level` against program and erase, 0 unlocking
+    /// the whole flash
+    pub fn set_block_protection(&mut self, level: u8) -> Result<(), Error<B::Error>> {
+        let (status, config) = (self.status()?, self.config()?);
+        self.write_status(status.with_block_protection(level), config)
+    }
+
+    /// Reads from `address` on four lines
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<B::Error>> {
+        Self::check_bounds(address, buffer.len())?;
+        let command = Command::new(Instruction::QuadIoRead)
+            .address(address, Lines::Quad)
+            .dummy_cycles(QUAD_IO_READ_DUMMY_CYCLES)
+            .data_lines(Lines::Quad);
+        self.backend.read(command, buffer).map_err(Error::Qspi)
+    }
+
+    /// Programs `data` within the page of `address`, the bytes being erased
+    pub fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error<B::Error>> {
+        Self::check_bounds(address, data.len())?;
+        if address % PAGE_SIZE + data.len() as u32 > PAGE_SIZE {
+            return Err(Error::NotAligned);
+        }
+        self.check_unprotected(address, data.len())?;
+        self.command(Instruction::WriteEnable)?;
+        let command = Command::new(Instruction::QuadPageProgram)
+            .address(address, Lines::Quad)
+            .data_lines(Lines::Quad);
+        self.backend.write(command, data).map_err(Error::Qspi)?;
+        self.wait_ready()?;
+        if self.security()?.program_failed() {
+            return Err(Error::ProgramFailed);
+        }
+        Ok(())
+    }
+
+    /// Programs `data` from `address` page by page
+    pub fn program(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), Error<B::Error>> {
+        Self::check_bounds(address, data.len())?;
+        while !data.is_empty() {
+            let len = data.len().min((PAGE_SIZE - address % PAGE_SIZE) as usize);
+            let (page, rest) = data.split_at(len);
+            self.program_page(address, page)?;
+            address += len as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Erases the 4K sector at `address`
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), Error<B::Error>> {
+        self.erase(Instruction::SectorErase, address, SECTOR_SIZE)
+    }
+
+    /// Erases the 32K block at `address`
+    pub fn erase_block_32k(&mut self, address: u32) -> Result<(), Error<B::Error>> {
+        self.erase(Instruction::Block32KErase, address, BLOCK_32K_SIZE)
+    }
+
+    /// Erases the 64K block at `address`
+    pub fn erase_block(&mut self, address: u32) -> Result<(), Error<B::Error>> {
+        self.erase(Instruction::BlockErase, address, BLOCK_SIZE)
+    }
+
+    pub fn erase_chip(&mut self) -> Result<(), Error<B::Error>> {
+        self.check_unprotected(0, CAPACITY as usize)?;
+        self.command(Instruction::WriteEnable)?;
+        self.command(Instruction::ChipErase)?;
+        self.wait_ready()?;
+        self.check_erased()
+    }
+
+    fn erase(
+        &mut self,
+        instruction: Instruction,
+        address: u32,
+        size: u32,
+    ) -> Result<(), Error<B::Error>> {
+        Self::check_bounds(address, size as usize)?;
+        if !address.is_multiple_of(size) {
+            return Err(Error::NotAligned);
+        }
+        self.check_unprotected(address, size as usize)?;
+        self.command(Instruction::WriteEnable)?;
+        self.backend
+            .write(
+                Command::new(instruction).address(address, Lines::Single),
+                &[],
+            )
+            .map_err(Error::Qspi)?;
+        self.wait_ready()?;
+        self.check_erased()
+    }
+
+    fn check_erased(&mut self) -> Result<(), Error<B::Error>> {
+        if self.security()?.erase_failed() {
+            return Err(Error::EraseFailed);
+        }
+        Ok(())
+    }
+
+    fn check_bounds(address: u32, len: usize) -> Result<(), Error<B::Error>> {
+        if address as usize + len > CAPACITY as usize {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    fn check_unprotected(&mut self, address: u32, len: usize) -> Result<(), Error<B::Error>> {
+        let protected = protected_range(self.block_protection()?);
+        if address as usize + len > protected.start as usize {
+            return Err(Error::Protected);
+        }
+        Ok(())
+    }
+
+    fn wait_ready(&mut self) -> Result<(), Error<B::Error>> {
+        while self.status()?.write_in_progress() {}
+        Ok(())
+    }
+
+    fn command(&mut self, instruction: Instruction) -> Result<(), Error<B::Error>> {
+        self.backend
+            .write(Command::new(instruction), &[])
+            .map_err(Error::Qspi)
+    }
+
+    fn read_register(&mut self, instruction: Instruction) -> Result<u8, Error<B::Error>> {
+        let mut value = [0];
+        self.backend
+            .read(Command::new(instruction), &mut value)
+            .map_err(Error::Qspi)?;
+        Ok(value[0])
+    }
+}
+
+impl<B: Backend> ErrorType for QspiFlash<B> {
+    type Error = Error<B::Error>;
+}
+
+impl<B: Backend> ReadNorFlash for QspiFlash<B> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        QspiFlash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl<B: Backend> NorFlash for QspiFlash<B> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    /// Erases with 64K blocks where aligned, 4K sectors otherwise
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to || to > CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        if !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        let mut address = from;
+        while address < to {
+            if address.is_multiple_of(BLOCK_SIZE) && to - address >= BLOCK_SIZE {
+                self.erase_block(address)?;
+                address += BLOCK_SIZE;
+            } else {
+                self.erase_sector(address)?;
+                address += SECTOR_SIZE;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.program(offset, bytes)
+    }
+}
+
+/// NOR bits can be programmed again from 1 to 0 without erasing
+impl<B: Backend> MultiwriteNorFlash for QspiFlash<B> {}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockBackend;
+    use super::*;
+
+    fn erased() -> Vec<u8> {
+        vec![0xFF; CAPACITY as usize]
+    }
+
+    fn flash(memory: &mut [u8]) -> QspiFlash<MockBackend<'_>> {
+        let mut flash = QspiFlash::new(MockBackend::new(memory));
+        flash.init().unwrap();
+        flash
+    }
+
+    #[test]
+    fn init_sets_quad_enable_once() {
+        let mut memory = erased();
+        let mut flash = QspiFlash::new(MockBackend::new(&mut memory));
+        flash.init().unwrap();
+        assert!(flash.status().unwrap().quad_enabled());
+        flash.init().unwrap();
+        assert_eq!(flash.backend_mut().sent(Instruction::WriteStatus), 1);
+    }
+
+    #[test]
+    fn program_splits_at_page_boundaries() {
+        let mut memory = erased();
+        let mut flash = flash(&mut memory);
+        let data: Vec<u8> = (0..20).collect();
+        assert_eq!(flash.program_page(250, &data), Err(Error::NotAligned));
+        flash.program(250, &data).unwrap();
+        assert_eq!(flash.backend_mut().sent(Instruction::QuadPageProgram), 2);
+
+        let backend = flash.release();
+        let memory = backend.memory();
+        assert_eq!(&memory[250..270], &data[..]);
+        // Not wrapped to the start of the first page
+        assert!(memory[..250].iter().all(|byte| *byte == 0xFF));
+        assert!(memory[270..512].iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn erase_uses_blocks_where_aligned() {
+        let mut memory = vec![0; CAPACITY as usize];
+        let mut flash = flash(&mut memory);
+        // Sector, 64K block, sector
+        let (from, to) = (BLOCK_SIZE - SECTOR_SIZE, 2 * BLOCK_SIZE + SECTOR_SIZE);
+        NorFlash::erase(&mut flash, from, to).unwrap();
+        assert_eq!(flash.backend_mut().sent(Instruction::SectorErase), 2);
+        assert_eq!(flash.backend_mut().sent(Instruction::BlockErase), 1);
+        assert_eq!(
+            NorFlash::erase(&mut flash, from + 1, to),
+            Err(Error::NotAligned)
+        );
+
+        let backend = flash.release();
+        let memory = backend.memory();
+        assert!(memory[from as usize..to as usize]
+            .iter()
+            .all(|byte| *byte == 0xFF));
+        assert_eq!(memory[from as usize - 1], 0);
+        assert_eq!(memory[to as usize], 0);
+    }
+
+    #[test]
+    fn protected_blocks_rejected() {
+        let mut memory = erased();
+        let mut flash = flash(&mut memory);
+        flash.set_block_protection(1).unwrap();
+        let protected = protected_range(1);
+        assert_eq!(protected, CAPACITY - BLOCK_SIZE..CAPACITY);
+
+        assert_eq!(
+            flash.program(protected.start, &[0; 4]),
+            Err(Error::Protected)
+        );
+        assert_eq!(flash.erase_sector(protected.start), Err(Error::Protected));
+        assert_eq!(flash.erase_chip(), Err(Error::Protected));
+        assert_eq!(
+            flash.erase_block_32k(protected.start - BLOCK_32K_SIZE),
+            Ok(())
+        );
+        // Crossing into the protected blocks
+        assert_eq!(
+            NorFlash::erase(&mut flash, protected.start - SECTOR_SIZE, CAPACITY),
+            Err(Error::Protected)
+        );
+        assert_eq!(flash.backend_mut().sent(Instruction::QuadPageProgram), 0);
+        assert_eq!(flash.backend_mut().sent(Instruction::ChipErase), 0);
+
+        flash.set_block_protection(0).unwrap();
+        flash.program(protected.start, &[0; 4]).unwrap();
+    }
+
+    #[test]
+    fn program_and_erase_failures() {
+        let mut memory = erased();
+        let mut flash = flash(&mut memory);
+        flash.backend_mut().fail_writes = true;
+        assert_eq!(flash.program(0, &[0; 4]), Err(Error::ProgramFailed));
+        assert_eq!(flash.erase_sector(0), Err(Error::EraseFailed));
+        assert_eq!(flash.erase_chip(), Err(Error::EraseFailed));
+
+        flash.backend_mut().fail_writes = false;
+        assert_eq!(flash.program(0, &[0; 4]), Ok(()));
+        assert_eq!(flash.erase_sector(0), Ok(()));
+    }
+}
@@ -1,10 +1,14 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub mod board;
 pub mod drivers;
-mod sys;
+#[cfg(all(feature = "panic-blink", not(test)))]
+pub mod panic;
+pub mod sys;
 pub use cortex_m_rt::entry;
 #[allow(unused)]
 use defmt_brtt as _;
+// `panic-blink` overrides the default `panic-probe`
+#[cfg(all(feature = "panic-probe", not(feature = "panic-blink"), not(test)))]
 use panic_probe as _;
 pub use stm32h7xx_hal as hal;
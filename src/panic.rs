@@ -0,0 +1,66 @@
+//! panic
+//!
+//! Panic handler for deployed boards, selected with the `panic-blink` feature
+//! The panic is logged through defmt, then the red LED (PK5) blinks SOS forever, or
+//! until the board resets if a delay was set with [`set_reset_delay`]
+//!
+
+use crate::drivers::led::Pattern;
+use crate::sys::ClockReport;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::peripheral::SCB;
+use fugit::MillisDurationU32;
+use stm32h7xx_hal::pac;
+
+/// No reset, blink forever
+const NO_RESET: u32 = u32::MAX;
+static RESET_DELAY_MS: AtomicU32 = AtomicU32::new(NO_RESET);
+
+/// Sets how long the blink code is shown before resetting, `None` blinks forever
+pub fn set_reset_delay(delay: Option<MillisDurationU32>) {
+    let ms = delay.map_or(NO_RESET, |delay| delay.ticks().min(NO_RESET - 1));
+    RESET_DELAY_MS.store(ms, Ordering::Relaxed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    defmt::error!("{}", defmt::Display2Format(info));
+
+    // Take over the user LEDs regardless of their previous configuration,
+    // red (PK5) as push-pull output, green and blue off
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    rcc.ahb4enr.modify(|_, w| w.gpioken().set_bit());
+    let gpiok = unsafe { &(*pac::GPIOK::ptr()) };
+    gpiok
+        .bsrr
+        .write(|w| w.bs5().set_bit().bs6().set_bit().bs7().set_bit());
+    gpiok.otyper.modify(|_, w| w.ot5().push_pull());
+    gpiok.moder.modify(|_, w| w.moder5().output());
+
+    // Timed from the clocks in use, whether set up, switched or fallen back to HSI
+    let cycles_per_ms = (ClockReport::capture().sys_ck.raw() / 1_000).max(1);
+    let reset_delay_ms = RESET_DELAY_MS.load(Ordering::Relaxed);
+    let mut elapsed_ms: u32 = 0;
+    let mut shown_ms: u32 = 0;
+    loop {
+        let (level, next_ms) = Pattern::Sos.level(elapsed_ms);
+        // LED is active low
+        if level == 0 {
+            gpiok.bsrr.write(|w| w.bs5().set_bit());
+        } else {
+            gpiok.bsrr.write(|w| w.br5().set_bit());
+        }
+        for _ in 0..next_ms {
+            cortex_m::asm::delay(cycles_per_ms);
+        }
+        elapsed_ms = elapsed_ms.wrapping_add(next_ms);
+        shown_ms = shown_ms.saturating_add(next_ms);
+        if reset_delay_ms != NO_RESET && shown_ms >= reset_delay_ms {
+            SCB::sys_reset();
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! cache
+//!
+//! Cortex-M7 instruction and data caches, and MPU regions setting the memory
+//! attributes of the RAM regions in memory.x
+//!
+
+use cortex_m::peripheral::{MPU, SCB};
+
+/// Data cache line size in bytes
+pub const CACHE_LINE: usize = 32;
+
+/// Maximum number of MPU regions on the Cortex-M7
+pub const MPU_REGIONS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Attributes {
+    /// Normal memory, not cached, e.g. DMA buffers
+    NonCacheable,
+    /// Normal memory, cached, writes go through to memory
+    WriteThrough,
+    /// Normal memory, cached with write-back and write-allocate
+    WriteBack,
+    /// Shareable device memory
+    Device,
+    StronglyOrdered,
+}
+
+impl Attributes {
+    /// MPU_RASR TEX, S, C and B bits
+    const fn rasr(self) -> u32 {
+        let (tex, s, c, b) = match self {
+            Self::NonCacheable => (0b001, 0, 0, 0),
+            Self::WriteThrough => (0b000, 0, 1, 0),
+            Self::WriteBack => (0b001, 0, 1, 1),
+            Self::Device => (0b000, 1, 0, 1),
+            Self::StronglyOrdered => (0b000, 0, 0, 0),
+        };
+        tex << 19 | s << 18 | c << 17 | b << 16
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Access {
+    ReadWrite,
+    ReadOnly,
+    /// Any access faults, e.g. memory not mapped yet
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct MpuRegion {
+    pub base: u32,
+    /// Size in bytes, a power of two from 32 bytes, `base` being aligned to it
+    pub size: u32,
+    pub attributes: Attributes,
+    pub access: Access,
+    pub executable: bool,
+}
+
+impl MpuRegion {
+    /// Read-write, non-executable region
+    pub const fn new(base: u32, size: u32, attributes: Attributes) -> Self {
+        Self {
+            base,
+            size,
+            attributes,
+            access: Access::ReadWrite,
+            executable: false,
+        }
+    }
+
+    pub const fn read_only(mut self) -> Self {
+        self.access = Access::ReadOnly;
+        self
+    }
+
+    pub const fn no_access(mut self) -> Self {
+        self.access = Access::None;
+        self
+    }
+
+    pub const fn executable(mut self) -> Self {
+        self.executable = true;
+        self
+    }
+
+    const fn is_valid(&self) -> bool {
+        self.size >= 32 && self.size.is_power_of_two() && self.base.is_multiple_of(self.size)
+    }
+
+    const fn rasr(&self) -> u32 {
+        let xn = if self.executable { 0 } else { 1 };
+        let ap = match self.access {
+            Access::ReadWrite => 0b011,
+            Access::ReadOnly => 0b110,
+            Access::None => 0b000,
+        };
+        let size = self.size.trailing_zeros() - 1;
+        xn << 28 | ap << 24 | self.attributes.rasr() | size << 1 | 1
+    }
+}
+
+/// RAM regions of memory.x, with their linker section
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum RamRegion {
+    /// `.axisram`, 512K
+    AxiSram,
+    /// `.sram1`, 128K
+    Sram1,
+    /// `.sram2`, 128K
+    Sram2,
+    /// `.sram3`, 32K
+    Sram3,
+    /// `.sram4`, 64K
+    Sram4,
+    /// `.sdram`, 8M, when the SDRAM is enabled
+    Sdram,
+}
+
+impl RamRegion {
+    pub const ALL: [Self; 6] = [
+        Self::AxiSram,
+        Self::Sram1,
+        Self::Sram2,
+        Self::Sram3,
+        Self::Sram4,
+        Self::Sdram,
+    ];
+
+    pub const fn base(self) -> u32 {
+        match self {
+            Self::AxiSram => 0x2400_0000,
+            Self::Sram1 => 0x3000_0000,
+            Self::Sram2 => 0x3002_0000,
+            Self::Sram3 => 0x3004_0000,
+            Self::Sram4 => 0x3800_0000,
+            Self::Sdram => 0xC000_0000,
+        }
+    }
+
+    pub const fn size(self) -> u32 {
+        match self {
+            Self::AxiSram => 512 * 1024,
+            Self::Sram1 | Self::Sram2 => 128 * 1024,
+            Self::Sram3 => 32 * 1024,
+            Self::Sram4 => 64 * 1024,
+            Self::Sdram => 8 * 1024 * 1024,
+        }
+    }
+
+    pub const fn mpu_region(self, attributes: Attributes) -> MpuRegion {
+        MpuRegion::new(self.base(), self.size(), attributes)
+    }
+}
+
+/// Programs the MPU with `regions`, higher indexes taking precedence where they
+/// overlap, and enables it with the default memory map as background
+pub fn configure_mpu(regions: impl IntoIterator<Item = MpuRegion>) {
+    let mpu = unsafe { &*MPU::PTR };
+    cortex_m::asm::dmb();
+    unsafe {
+        mpu.ctrl.write(0);
+        let mut count = 0;
+        for region in regions {
+            assert!(count < MPU_REGIONS, "too many MPU regions");
+            assert!(region.is_valid(), "invalid MPU region");
+            mpu.rnr.write(count as u32);
+            mpu.rbar.write(region.base);
+            mpu.rasr.write(region.rasr());
+            count += 1;
+        }
+        for unused in count..MPU_REGIONS {
+            mpu.rnr.write(unused as u32);
+            mpu.rasr.write(0);
+        }
+        // ENABLE and PRIVDEFENA
+        mpu.ctrl.write(1 << 2 | 1);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Reprograms the region set by [`configure_mpu`] at the base of `region`, e.g. to
+/// change the access to memory mapped at runtime
+pub fn reconfigure_mpu_region(region: MpuRegion) {
+    let mpu = unsafe { &*MPU::PTR };
+    assert!(region.is_valid(), "invalid MPU region");
+    critical_section::with(|_| {
+        let number = (0..MPU_REGIONS as u32)
+            .find(|number| unsafe {
+                mpu.rnr.write(*number);
+                // ENABLE, and RBAR ADDR
+                mpu.rasr.read() & 1 != 0 && mpu.rbar.read() & !0x1F == region.base
+            })
+            .expect("MPU region not configured");
+        cortex_m::asm::dmb();
+        unsafe {
+            mpu.rnr.write(number);
+            mpu.rasr.write(region.rasr());
+        }
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    });
+}
+
+pub fn enable_caches() {
+    let mut cp = unsafe { cortex_m::Peripherals::steal() };
+    cp.SCB.enable_icache();
+    cp.SCB.enable_dcache(&mut cp.CPUID);
+}
+
+/// Writes the cached content of `buffer` to memory, before a DMA reads it
+pub fn clean_dcache<T: ?Sized>(buffer: &T) {
+    if SCB::dcache_enabled() {
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+        cp.SCB.clean_dcache_by_address(
+            buffer as *const T as *const u8 as usize,
+            core::mem::size_of_val(buffer),
+        );
+    }
+}
+
+/// Discards the cached content of `buffer`, after a DMA wrote it
+///
+/// `buffer` must be aligned to and span whole [`CACHE_LINE`]s, otherwise data sharing
+/// its first or last line would be lost
+pub fn invalidate_dcache<T: ?Sized>(buffer: &mut T) {
+    let (address, size) = (
+        buffer as *mut T as *mut u8 as usize,
+        core::mem::size_of_val(buffer),
+    );
+    assert!(address.is_multiple_of(CACHE_LINE) && size.is_multiple_of(CACHE_LINE));
+    if SCB::dcache_enabled() {
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+        unsafe { cp.SCB.invalidate_dcache_by_address(address, size) };
+    }
+}
+
+/// Writes the cached content of `buffer` to memory and discards it
+pub fn clean_invalidate_dcache<T: ?Sized>(buffer: &mut T) {
+    if SCB::dcache_enabled() {
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+        cp.SCB.clean_invalidate_dcache_by_address(
+            buffer as *mut T as *mut u8 as usize,
+            core::mem::size_of_val(buffer),
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! css
+//!
+//! HSE clock security system
+//! On an oscillator failure the hardware stops HSE, switches the system clock to HSI
+//! and raises the NMI, from which [`on_nmi`] restarts PLL1 from HSI and records the
+//! failure in backup SRAM, reported from thread mode by [`poll`]
+//! The application must call [`on_nmi`] from its `NonMaskableInt` handler, the
+//! default handler spinning forever
+//!
+
+use crate::sys::{cache, ClockReport, Clocks};
+use core::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use cortex_m::peripheral::SCB;
+use stm32h7xx_hal::pac;
+
+/// Failure handled by the NMI, not yet reported by [`poll`]
+static PENDING: AtomicBool = AtomicBool::new(false);
+static CALLBACK: AtomicUsize = AtomicUsize::new(0);
+
+const FAILED_MAGIC: u32 = 0x4E5E_FA11;
+
+/// HSE failure record, at a fixed offset of the backup SRAM in memory.x so it stays in
+/// place across firmware updates
+#[link_section = ".bsram.css"]
+static mut FAILED: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// PLL1 (DIVM, DIVN, DIVP) from the 64 MHz HSI, 200 MHz valid in every voltage scale
+const FALLBACK_PLL1: (u8, u16, u8) = (4, 25, 2);
+
+/// RCC polls before resetting the system, milliseconds from HSI
+const TIMEOUT_POLLS: u32 = 100_000;
+
+/// Enables the clock security system, it can only be disabled by a reset
+pub fn enable() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    rcc.cr.modify(|_, w| w.hsecsson().on());
+}
+
+/// Sets a function called by [`poll`] with the fallback clocks, after a failure
+pub fn set_callback(callback: fn(&Clocks)) {
+    CALLBACK.store(callback as usize, Ordering::Release);
+}
+
+/// Whether HSE failed since the last [`clear_failure`], before a reset included
+///
+/// The failure is recorded in backup SRAM, kept across resets while the backup domain
+/// is powered.
+pub fn hse_failed() -> bool {
+    unsafe { record().read_volatile() == FAILED_MAGIC }
+}
+
+pub fn clear_failure() {
+    write_record(0);
+}
+
+/// Handles an HSE failure, to be called from the `NonMaskableInt` exception handler
+///
+/// Only the clocks are switched and the failure recorded here, it is logged and
+/// reported to the callback by [`poll`], outside of the NMI.
+///
+/// ```ignore
+/// #[exception]
+/// unsafe fn NonMaskableInt() {
+///     portenta_h7::sys::css::on_nmi();
+/// }
+/// ```
+pub fn on_nmi() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    if rcc.cifr.read().hsecssf().bit_is_clear() {
+        return;
+    }
+    rcc.cicr.write(|w| w.hsecssc().clear());
+
+    // Recorded first, to be reported if the fallback resets the system
+    write_record(FAILED_MAGIC);
+    fallback();
+    PENDING.store(true, Ordering::Release);
+}
+
+/// Reports an HSE failure handled by [`on_nmi`] since the last call, to be called
+/// periodically from thread mode or a task
+///
+/// Logs the failure and calls the callback, returning the fallback clocks the
+/// peripherals must be told about.
+pub fn poll() -> Option<Clocks> {
+    if !PENDING.swap(false, Ordering::AcqRel) {
+        return None;
+    }
+    let clocks = ClockReport::capture().clocks();
+    defmt::error!("HSE failure, running from HSI: {}", clocks);
+
+    let callback = CALLBACK.load(Ordering::Acquire);
+    if callback != 0 {
+        let callback: fn(&Clocks) = unsafe { core::mem::transmute(callback) };
+        callback(&clocks);
+    }
+    Some(clocks)
+}
+
+fn record() -> *mut u32 {
+    ptr::addr_of_mut!(FAILED) as *mut u32
+}
+
+/// Writes the record through to the backup SRAM, so a reset does not lose it
+fn write_record(value: u32) {
+    unsafe { record().write_volatile(value) };
+    cache::clean_dcache(unsafe { &*ptr::addr_of!(FAILED) });
+}
+
+/// Restarts PLL1 from HSI, keeping the bus prescalers, flash latency and voltage
+/// scale, which were set for a faster system clock
+///
+/// PLL2 and PLL3 are stopped, their clock source being the failed HSE, as PLLSRC can
+/// only change while all PLLs are off. The system is reset if the RCC does not
+/// respond.
+fn fallback() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    let (divm, divn, divp) = FALLBACK_PLL1;
+
+    rcc.cr
+        .modify(|_, w| w.pll1on().off().pll2on().off().pll3on().off());
+    wait(|| {
+        let cr = rcc.cr.read();
+        cr.pll1rdy().is_not_ready() && cr.pll2rdy().is_not_ready() && cr.pll3rdy().is_not_ready()
+    });
+    rcc.pllckselr
+        .modify(|_, w| w.pllsrc().hsi().divm1().bits(divm));
+    rcc.pll1divr
+        .modify(|_, w| unsafe { w.divn1().bits(divn - 1).divp1().bits(divp - 1) });
+    rcc.pllcfgr.modify(|_, w| {
+        w.pll1rge()
+            .range8()
+            .pll1vcosel()
+            .wide_vco()
+            .pll1fracen()
+            .reset()
+            .divp1en()
+            .enabled()
+    });
+    rcc.cr.modify(|_, w| w.pll1on().on());
+    wait(|| rcc.cr.read().pll1rdy().is_ready());
+    rcc.cfgr.modify(|_, w| w.sw().pll1());
+    wait(|| rcc.cfgr.read().sws().is_pll1());
+}
+
+/// Polls `ready`, resetting the system rather than hanging in the NMI
+fn wait(ready: impl Fn() -> bool) {
+    if !(0..TIMEOUT_POLLS).any(|_| ready()) {
+        SCB::sys_reset();
+    }
+}
@@ -0,0 +1,124 @@
+//! handover
+//!
+//! Takes the MCU over from the Arduino bootloader, which leaves the core and
+//! peripherals it used for DFU configured
+//!
+
+use crate::sys::{Clk, Reset};
+use cortex_m::peripheral::SCB;
+use stm32h7xx_hal::pac;
+
+extern "C" {
+    /// Start of the vector table, placed by `link.x` at the origin of FLASH in memory.x
+    static __vector_table: u32;
+}
+
+/// Address of the vector table of the application, as linked
+pub fn app_vector_table() -> u32 {
+    core::ptr::addr_of!(__vector_table) as u32
+}
+
+// Resettable peripherals of each bus
+const AHB1RSTR_MASK: u32 = 0x0A00_C023;
+const AHB2RSTR_MASK: u32 = 0x0000_0271;
+const AHB3RSTR_MASK: u32 = 0x0001_5031;
+const AHB4RSTR_MASK: u32 = 0x0328_07FF;
+const APB1LRSTR_MASK: u32 = 0xE8FF_C3FF;
+const APB1HRSTR_MASK: u32 = 0x0000_0136;
+const APB2RSTR_MASK: u32 = 0x31D7_3033;
+const APB3RSTR_MASK: u32 = 0x0000_0018;
+const APB4RSTR_MASK: u32 = 0x0020_DEAA;
+/// SRAM1, SRAM2 and SRAM3 clocks, kept enabled as they may hold application data
+const AHB2ENR_SRAM_MASK: u32 = 0xE000_0000;
+
+/// Returns the core and peripherals to their reset state
+///
+/// Clocks are reset first, so the system runs from HSI while peripherals, GPIOH
+/// included, are reset. The external oscillator must then be enabled again.
+/// SysTick is stopped, so monotonics based on it must be started afterwards, and
+/// interrupts pended before are lost.
+pub fn handover() -> Clk<Reset> {
+    cortex_m::interrupt::free(|_| reset_state())
+}
+
+fn reset_state() -> Clk<Reset> {
+    let clk = Clk::new().reset();
+
+    // Core
+    let mut cp = unsafe { cortex_m::Peripherals::steal() };
+    cp.SYST.disable_interrupt();
+    cp.SYST.disable_counter();
+    unsafe {
+        cp.SYST.rvr.write(0);
+        cp.SYST.cvr.write(0);
+    }
+    SCB::clear_pendst();
+    SCB::clear_pendsv();
+
+    unsafe {
+        cp.MPU.ctrl.write(0);
+        for region in 0..(cp.MPU._type.read() >> 8) & 0xFF {
+            cp.MPU.rnr.write(region);
+            cp.MPU.rasr.write(0);
+            cp.MPU.rbar.write(0);
+        }
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    cp.SCB.disable_dcache(&mut cp.CPUID);
+    cp.SCB.disable_icache();
+
+    // Peripherals, pulsing their reset and gating their clocks
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    unsafe {
+        rcc.ahb1rstr.write(|w| w.bits(AHB1RSTR_MASK));
+        rcc.ahb2rstr.write(|w| w.bits(AHB2RSTR_MASK));
+        rcc.ahb3rstr.write(|w| w.bits(AHB3RSTR_MASK));
+        rcc.ahb4rstr.write(|w| w.bits(AHB4RSTR_MASK));
+        rcc.apb1lrstr.write(|w| w.bits(APB1LRSTR_MASK));
+        rcc.apb1hrstr.write(|w| w.bits(APB1HRSTR_MASK));
+        rcc.apb2rstr.write(|w| w.bits(APB2RSTR_MASK));
+        rcc.apb3rstr.write(|w| w.bits(APB3RSTR_MASK));
+        rcc.apb4rstr.write(|w| w.bits(APB4RSTR_MASK));
+    }
+    rcc.ahb1rstr.reset();
+    rcc.ahb2rstr.reset();
+    rcc.ahb3rstr.reset();
+    rcc.ahb4rstr.reset();
+    rcc.apb1lrstr.reset();
+    rcc.apb1hrstr.reset();
+    rcc.apb2rstr.reset();
+    rcc.apb3rstr.reset();
+    rcc.apb4rstr.reset();
+
+    rcc.ahb1enr.reset();
+    rcc.ahb2enr
+        .modify(|r, w| unsafe { w.bits(r.bits() & AHB2ENR_SRAM_MASK) });
+    rcc.ahb3enr.reset();
+    rcc.ahb4enr.reset();
+    rcc.apb1lenr.reset();
+    rcc.apb1henr.reset();
+    rcc.apb2enr.reset();
+    rcc.apb3enr.reset();
+    rcc.apb4enr.reset();
+
+    // Interrupts left pending by the peripherals are cleared once they are reset,
+    // enabled interrupts and their priorities may already be set by the application
+    unsafe {
+        for icpr in cp.NVIC.icpr.iter() {
+            icpr.write(u32::MAX);
+        }
+    }
+
+    let vtor = cp.SCB.vtor.read();
+    let vector_table = app_vector_table();
+    if vtor != vector_table {
+        defmt::warn!("VTOR at {:#X}, relocating to {:#X}", vtor, vector_table);
+        unsafe { cp.SCB.vtor.write(vector_table) };
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    clk
+}
@@ -0,0 +1,69 @@
+//! itcm
+//!
+//! Code run from the zero-wait-state ITCM, see [`itcm!`]
+//! The `.itcm` section is loaded in flash and copied to ITCM by [`init`], at board setup
+//!
+//! [`itcm!`]: crate::itcm
+//!
+
+extern "C" {
+    static mut __sitcm: u32;
+    static mut __eitcm: u32;
+    static __siitcm: u32;
+}
+
+/// Addresses of the 64K ITCM
+pub const ITCM_RANGE: core::ops::Range<usize> = 0x0000_0000..0x0001_0000;
+
+/// Whether `function`, e.g. `handler as *const ()`, runs from ITCM
+pub fn runs_from_itcm(function: *const ()) -> bool {
+    ITCM_RANGE.contains(&(function as usize))
+}
+
+/// Copies the `.itcm` section from flash to ITCM
+///
+/// Done first at board setup, functions of the section must not run before. Copying
+/// again rewrites the same code, so this can be called earlier when needed.
+pub fn init() {
+    unsafe {
+        let start = core::ptr::addr_of_mut!(__sitcm);
+        let end = core::ptr::addr_of_mut!(__eitcm);
+        let words = end.offset_from(start) as usize;
+        core::ptr::copy_nonoverlapping(core::ptr::addr_of!(__siitcm), start, words);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Places functions in the `.itcm` section, e.g. ISRs and DSP loops, without inlining
+/// them in callers from flash
+///
+/// ```ignore
+/// itcm! {
+///     fn filter(samples: &mut [i16]) {
+///         // ...
+///     }
+/// }
+/// ```
+///
+/// Attributes on an RTIC task only place the wrapper RTIC generates, the task calls
+/// a function placed here instead, which can be checked with [`runs_from_itcm`].
+/// Functions it calls from other crates stay in flash unless inlined.
+///
+/// ```ignore
+/// #[task(binds = OTG_HS, local = [usb_dev])]
+/// fn usb_process(cx: usb_process::Context) {
+///     poll_usb(cx.local.usb_dev);
+/// }
+/// ```
+#[macro_export]
+macro_rules! itcm {
+    ($($(#[$attr:meta])* $vis:vis fn $name:ident $args:tt $(-> $ret:ty)? $body:block)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".itcm"]
+            #[inline(never)]
+            $vis fn $name $args $(-> $ret)? $body
+        )*
+    };
+}
@@ -0,0 +1,197 @@
+//! sys
+//!
+//! Clear up previous clock initialization done in bootloader
+//! Enable external oscillator for HSE sourcing (25 MHz)
+//! Clock profiles, switchable at runtime
+//! Clock tree report decoded from the RCC registers
+//! HSE clock security system with fallback to HSI
+//! Handover from the bootloader, see [`handover`]
+//! Caches and MPU regions
+//! Static placement in the RAM regions
+//! Code run from ITCM
+//! Reset cause
+//!
+
+#![allow(dead_code)]
+
+use fugit::HertzU32;
+use stm32h7xx_hal::pac;
+
+pub mod cache;
+pub mod css;
+mod handover;
+pub mod itcm;
+mod profile;
+pub mod ram;
+mod report;
+mod reset;
+
+pub use handover::{app_vector_table, handover};
+pub use profile::{
+    switch_profile, usb_hs_clock_valid, ClockChangeListener, ClockError, ClockProfile, ClockSource,
+    Clocks, VoltageScale, USB_HS_MIN_HCLK,
+};
+pub use report::{
+    ClockReport, KernelClock, KernelClocks, KernelSource, PllOutputs, RccSnapshot, SysClkSource,
+};
+pub use reset::{Core, ResetReason};
+
+pub const HSE_FREQUENCY: HertzU32 = HertzU32::from_raw(25_000_000);
+pub const HSI_FREQUENCY: HertzU32 = HertzU32::from_raw(64_000_000);
+
+pub struct Unreset;
+pub struct Reset;
+
+pub struct Clk<State> {
+    _state: State,
+}
+
+pub type ClkSource = pac::rcc::cfgr::SWS_A;
+pub type ClkSourceVariant = Option<ClkSource>;
+pub type PllSourceVariant = pac::rcc::pllckselr::PLLSRC_A;
+
+impl Clk<Unreset> {
+    pub fn new() -> Clk<Unreset> {
+        Clk { _state: Unreset }
+    }
+
+    #[inline(always)]
+    pub fn get_source() -> ClkSourceVariant {
+        unsafe { (*pac::RCC::ptr()).cfgr.read().sws().variant() }
+    }
+
+    #[inline(always)]
+    pub fn get_pll_source() -> PllSourceVariant {
+        unsafe { (*pac::RCC::ptr()).pllckselr.read().pllsrc().variant() }
+    }
+
+    pub fn reset(self) -> Clk<Reset> {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+
+        // Enable HSI and load reset values
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        rcc.hsicfgr.reset();
+        // Reset clock configuration and wait for clock switch
+        rcc.cfgr.reset();
+        while !rcc.cfgr.read().sws().is_hsi() {}
+        // Reset CSI, HSE, HSI48 and dividers
+        rcc.cr.modify(|_, w| {
+            w.hseon()
+                .off()
+                .hsikeron()
+                .off()
+                .hsidiv()
+                .div1()
+                .hsidivf()
+                .clear_bit()
+                .csion()
+                .off()
+                .csikeron()
+                .off()
+                .hsi48on()
+                .off()
+                .hsecsson()
+                .off()
+                .hsebyp()
+                .clear_bit()
+        });
+        while rcc.cr.read().hserdy().bit_is_set() {}
+        // Disable PLL1
+        rcc.cr.modify(|_, w| w.pll1on().clear_bit());
+        while rcc.cr.read().pll1rdy().bit_is_set() {}
+        // Disable PLL2
+        rcc.cr.modify(|_, w| w.pll2on().clear_bit());
+        while rcc.cr.read().pll2rdy().bit_is_set() {}
+        // Disable PLL3
+        rcc.cr.modify(|_, w| w.pll3on().clear_bit());
+        while rcc.cr.read().pll3rdy().bit_is_set() {}
+        // Reset domain configurations
+        rcc.d1cfgr.reset();
+        rcc.d2cfgr.reset();
+        rcc.d3cfgr.reset();
+        // Reset PLLs configurations
+        rcc.pllckselr.reset();
+        rcc.pll1divr.reset();
+        rcc.pll1fracr.reset();
+        rcc.pll2divr.reset();
+        rcc.pll2fracr.reset();
+        rcc.pll3fracr.reset();
+        Clk { _state: Reset }
+    }
+}
+
+impl Default for Clk<Unreset> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clk<Reset> {
+    pub fn enable_ext_clock(self) -> Clk<Reset> {
+        power_ext_oscillator();
+        Clk { _state: Reset }
+    }
+}
+
+impl RccSnapshot {
+    pub fn capture() -> Self {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        Self {
+            cr: rcc.cr.read().bits(),
+            cfgr: rcc.cfgr.read().bits(),
+            d1cfgr: rcc.d1cfgr.read().bits(),
+            d2cfgr: rcc.d2cfgr.read().bits(),
+            d3cfgr: rcc.d3cfgr.read().bits(),
+            pllckselr: rcc.pllckselr.read().bits(),
+            pllcfgr: rcc.pllcfgr.read().bits(),
+            pll1divr: rcc.pll1divr.read().bits(),
+            pll1fracr: rcc.pll1fracr.read().bits(),
+            pll2divr: rcc.pll2divr.read().bits(),
+            pll2fracr: rcc.pll2fracr.read().bits(),
+            pll3divr: rcc.pll3divr.read().bits(),
+            pll3fracr: rcc.pll3fracr.read().bits(),
+            d1ccipr: rcc.d1ccipr.read().bits(),
+            d2ccip1r: rcc.d2ccip1r.read().bits(),
+            d2ccip2r: rcc.d2ccip2r.read().bits(),
+            d3ccipr: rcc.d3ccipr.read().bits(),
+        }
+    }
+}
+
+impl ClockReport {
+    /// Decodes the current RCC configuration
+    pub fn capture() -> Self {
+        Self::decode(&RccSnapshot::capture(), HSE_FREQUENCY)
+    }
+
+    pub fn clocks(&self) -> Clocks {
+        Clocks {
+            sys_ck: self.sys_ck,
+            hclk: self.hclk,
+            pclk1: self.pclk1,
+            pclk2: self.pclk2,
+            pclk3: self.pclk3,
+            pclk4: self.pclk4,
+            timx_ker_ck: self.timx_ker_ck,
+        }
+    }
+}
+
+/// Powers the external oscillator (HSE source) through GPIOH_1
+pub(crate) fn power_ext_oscillator() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    // Enable GPIOH clock
+    rcc.ahb4enr.modify(|_, w| w.gpiohen().set_bit());
+
+    // Enable oscilator via push pulled GPIOH_1 output
+    let gpioh = unsafe { &(*pac::GPIOH::ptr()) };
+    gpioh.bsrr.write(|w| w.bs1().set_bit());
+    gpioh.moder.modify(|_, w| w.moder1().output());
+    gpioh.otyper.modify(|_, w| w.ot1().push_pull());
+    gpioh.ospeedr.modify(|_, w| w.ospeedr1().low_speed());
+    gpioh.pupdr.modify(|_, w| w.pupdr1().pull_up());
+
+    // Wait for stabilization
+    const CYCLES_TO_WAIT: u32 = 15_000;
+    cortex_m::asm::delay(CYCLES_TO_WAIT);
+}
@@ -0,0 +1,318 @@
+//! profile
+//!
+//! Predefined clock profiles, selected at startup through the board configuration
+//! and switchable at runtime with [`switch_profile`]
+//!
+
+use crate::sys::{power_ext_oscillator, ClockReport, HSE_FREQUENCY, HSI_FREQUENCY};
+use cortex_m::peripheral::SYST;
+use fugit::HertzU32;
+use stm32h7xx_hal::pac;
+
+/// Lowest AHB clock the USB HS core supports with the ULPI PHY
+pub const USB_HS_MIN_HCLK: HertzU32 = HertzU32::from_raw(30_000_000);
+
+/// Core voltage scaling, VOS0 is required above 400 MHz
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum VoltageScale {
+    Vos3,
+    Vos2,
+    Vos1,
+    Vos0,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClockSource {
+    /// Internal 64 MHz oscillator, without PLL
+    Hsi,
+    /// External 25 MHz oscillator through PLL1
+    Hse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClockProfile {
+    /// 480 MHz core, 240 MHz AHB, 120 MHz APB
+    Performance,
+    /// 200 MHz core, 100 MHz AHB, 50 MHz APB
+    Balanced,
+    /// 64 MHz core and AHB from HSI, 32 MHz APB, HSE off
+    LowPower,
+}
+
+impl ClockProfile {
+    pub const ALL: [Self; 3] = [Self::Performance, Self::Balanced, Self::LowPower];
+
+    pub const fn sys_ck(self) -> HertzU32 {
+        match self {
+            Self::Performance => HertzU32::from_raw(480_000_000),
+            Self::Balanced => HertzU32::from_raw(200_000_000),
+            Self::LowPower => HSI_FREQUENCY,
+        }
+    }
+
+    pub const fn hclk(self) -> HertzU32 {
+        match self {
+            Self::Performance => HertzU32::from_raw(240_000_000),
+            Self::Balanced => HertzU32::from_raw(100_000_000),
+            Self::LowPower => HSI_FREQUENCY,
+        }
+    }
+
+    /// Clock of every APB bus
+    pub const fn pclk(self) -> HertzU32 {
+        HertzU32::from_raw(self.hclk().raw() / 2)
+    }
+
+    pub const fn vos(self) -> VoltageScale {
+        match self {
+            Self::Performance => VoltageScale::Vos0,
+            Self::Balanced => VoltageScale::Vos2,
+            Self::LowPower => VoltageScale::Vos3,
+        }
+    }
+
+    pub const fn source(self) -> ClockSource {
+        match self {
+            Self::Performance | Self::Balanced => ClockSource::Hse,
+            Self::LowPower => ClockSource::Hsi,
+        }
+    }
+
+    /// Whether USB HS may stay enabled in this profile, the AHB clock being fast
+    /// enough, see [`USB_HS_MIN_HCLK`]
+    pub const fn keeps_usb(self) -> bool {
+        usb_hs_clock_valid(&self.clocks())
+    }
+
+    pub const fn clocks(self) -> Clocks {
+        let pclk = self.pclk();
+        Clocks {
+            sys_ck: self.sys_ck(),
+            hclk: self.hclk(),
+            pclk1: pclk,
+            pclk2: pclk,
+            pclk3: pclk,
+            pclk4: pclk,
+            // APB timers run at twice the APB clock when the APB prescaler is not 1
+            timx_ker_ck: HertzU32::from_raw(pclk.raw() * 2),
+        }
+    }
+
+    /// PLL1 (DIVM, DIVN, DIVP) from the 25 MHz HSE, with a 5 MHz reference
+    const fn pll1(self) -> Option<(u8, u16, u8)> {
+        match self {
+            Self::Performance => Some((5, 192, 2)),
+            Self::Balanced => Some((5, 80, 2)),
+            Self::LowPower => None,
+        }
+    }
+
+    /// Flash (LATENCY, WRHIGHFREQ) for the AXI clock at the profile voltage scale
+    const fn flash_latency(self) -> (u8, u8) {
+        match self {
+            Self::Performance => (4, 2),
+            Self::Balanced | Self::LowPower => (1, 1),
+        }
+    }
+}
+
+const _: () = {
+    let mut i = 0;
+    while i < ClockProfile::ALL.len() {
+        let profile = ClockProfile::ALL[i];
+        if let Some((divm, divn, divp)) = profile.pll1() {
+            let vco = HSE_FREQUENCY.raw() / divm as u32 * divn as u32;
+            assert!(vco / divp as u32 == profile.sys_ck().raw());
+        }
+        i += 1;
+    }
+};
+
+/// Bus clocks, as seen by the peripheral drivers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Clocks {
+    pub sys_ck: HertzU32,
+    pub hclk: HertzU32,
+    pub pclk1: HertzU32,
+    pub pclk2: HertzU32,
+    pub pclk3: HertzU32,
+    pub pclk4: HertzU32,
+    /// Kernel clock of the APB1 and APB2 timers
+    pub timx_ker_ck: HertzU32,
+}
+
+pub const fn usb_hs_clock_valid(clocks: &Clocks) -> bool {
+    clocks.hclk.raw() >= USB_HS_MIN_HCLK.raw()
+}
+
+/// Driver depending on the bus clocks, to be told when they change
+pub trait ClockChangeListener {
+    fn clocks_changed(&mut self, clocks: &Clocks);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClockError {
+    /// USB HS is enabled and the profile does not keep it clocked
+    UsbClock,
+    /// SysTick is running, e.g. as the RTIC monotonic, and the profile changes the
+    /// core clock it counts
+    SysTickRunning,
+}
+
+/// Switches the clock tree to `profile` and notifies `listeners`
+///
+/// The core runs from HSI while PLL1 is reconfigured. Peripherals clocked from PLL1
+/// outputs other than P must be reconfigured by the application.
+///
+/// SysTick based monotonics cannot be rescaled: while SysTick is enabled, only
+/// profiles keeping the current core clock are accepted.
+pub fn switch_profile(
+    profile: ClockProfile,
+    listeners: &mut [&mut dyn ClockChangeListener],
+) -> Result<Clocks, ClockError> {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    let flash = unsafe { &(*pac::FLASH::ptr()) };
+
+    if rcc.ahb1enr.read().usb1otgen().bit_is_set() && !profile.keeps_usb() {
+        return Err(ClockError::UsbClock);
+    }
+    let syst = unsafe { &(*SYST::PTR) };
+    // CSR ENABLE
+    if syst.csr.read() & 1 != 0 && ClockReport::capture().sys_ck != profile.sys_ck() {
+        return Err(ClockError::SysTickRunning);
+    }
+
+    // Raise the core voltage before the frequency
+    let vos = current_vos();
+    if profile.vos() > vos {
+        set_vos(profile.vos());
+    }
+
+    // Run from HSI while the clock tree is reconfigured
+    rcc.cr.modify(|_, w| w.hsion().on().hsidiv().div1());
+    while rcc.cr.read().hsirdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().hsi());
+    while !rcc.cfgr.read().sws().is_hsi() {}
+
+    let (latency, wrhighfreq) = profile.flash_latency();
+    if latency > flash.acr.read().latency().bits() {
+        flash
+            .acr
+            .write(|w| unsafe { w.latency().bits(latency).wrhighfreq().bits(wrhighfreq) });
+        while flash.acr.read().latency().bits() != latency {}
+    }
+
+    // AHB divided by 1 or 2, every APB by 2
+    let hpre = if profile.hclk().raw() == profile.sys_ck().raw() {
+        0b0000
+    } else {
+        0b1000
+    };
+    rcc.d1cfgr.modify(|_, w| unsafe {
+        w.d1cpre()
+            .bits(0b0000)
+            .hpre()
+            .bits(hpre)
+            .d1ppre()
+            .bits(0b100)
+    });
+    rcc.d2cfgr
+        .modify(|_, w| unsafe { w.d2ppre1().bits(0b100).d2ppre2().bits(0b100) });
+    rcc.d3cfgr.modify(|_, w| unsafe { w.d3ppre().bits(0b100) });
+
+    rcc.cr.modify(|_, w| w.pll1on().off());
+    while rcc.cr.read().pll1rdy().is_ready() {}
+    match profile.pll1() {
+        Some((divm, divn, divp)) => {
+            enable_hse();
+            rcc.pllckselr
+                .modify(|_, w| w.pllsrc().hse().divm1().bits(divm));
+            rcc.pll1divr
+                .modify(|_, w| unsafe { w.divn1().bits(divn - 1).divp1().bits(divp - 1) });
+            rcc.pllcfgr.modify(|_, w| {
+                w.pll1rge()
+                    .range4()
+                    .pll1vcosel()
+                    .wide_vco()
+                    .pll1fracen()
+                    .reset()
+                    .divp1en()
+                    .enabled()
+            });
+            rcc.cr.modify(|_, w| w.pll1on().on());
+            while rcc.cr.read().pll1rdy().is_not_ready() {}
+            rcc.cfgr.modify(|_, w| w.sw().pll1());
+            while !rcc.cfgr.read().sws().is_pll1() {}
+        }
+        None => {
+            // Keep HSE running if other PLLs still use it
+            let cr = rcc.cr.read();
+            if cr.pll2on().is_off() && cr.pll3on().is_off() {
+                rcc.cr.modify(|_, w| w.hseon().off());
+            }
+        }
+    }
+
+    if latency < flash.acr.read().latency().bits() {
+        flash
+            .acr
+            .write(|w| unsafe { w.latency().bits(latency).wrhighfreq().bits(wrhighfreq) });
+        while flash.acr.read().latency().bits() != latency {}
+    }
+
+    // Lower the core voltage after the frequency
+    if profile.vos() < vos {
+        set_vos(profile.vos());
+    }
+
+    let clocks = profile.clocks();
+    for listener in listeners.iter_mut() {
+        listener.clocks_changed(&clocks);
+    }
+    Ok(clocks)
+}
+
+fn enable_hse() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    if rcc.cr.read().hserdy().is_ready() {
+        return;
+    }
+    power_ext_oscillator();
+    rcc.cr.modify(|_, w| w.hsebyp().bypassed().hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+}
+
+fn current_vos() -> VoltageScale {
+    let pwr = unsafe { &(*pac::PWR::ptr()) };
+    let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+    match pwr.d3cr.read().vos().bits() {
+        0b11 if syscfg.pwrcr.read().oden().bit_is_set() => VoltageScale::Vos0,
+        0b11 => VoltageScale::Vos1,
+        0b10 => VoltageScale::Vos2,
+        _ => VoltageScale::Vos3,
+    }
+}
+
+fn set_vos(vos: VoltageScale) {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    let pwr = unsafe { &(*pac::PWR::ptr()) };
+    let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+    rcc.apb4enr.modify(|_, w| w.syscfgen().enabled());
+
+    // Leave overdrive before changing the scale
+    if syscfg.pwrcr.read().oden().bit_is_set() {
+        syscfg.pwrcr.modify(|_, w| w.oden().clear_bit());
+        while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+    }
+    let bits = match vos {
+        VoltageScale::Vos0 | VoltageScale::Vos1 => 0b11,
+        VoltageScale::Vos2 => 0b10,
+        VoltageScale::Vos3 => 0b01,
+    };
+    pwr.d3cr.modify(|_, w| unsafe { w.vos().bits(bits) });
+    while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+    if vos == VoltageScale::Vos0 {
+        syscfg.pwrcr.modify(|_, w| w.oden().set_bit());
+        while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+    }
+}
@@ -0,0 +1,204 @@
+//! ram
+//!
+//! Static placement in the RAM regions of memory.x, see [`ram_static!`] and
+//! [`dma_static!`]
+//! The sections are NOLOAD, so placed statics are written at runtime when taken
+//!
+//! [`ram_static!`]: crate::ram_static
+//! [`dma_static!`]: crate::dma_static
+//!
+
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use stm32h7xx_hal::pac;
+
+/// RAM region with its own linker section
+pub trait Region {
+    /// Enables the clock of the region if it is gated
+    fn enable() {}
+}
+
+/// Region reachable by DMA1, DMA2, MDMA and the USB HS DMA
+///
+/// # Safety
+///
+/// Only implemented for regions those bus masters can access
+pub unsafe trait DmaReachable: Region {}
+
+/// Region reachable by BDMA, in the D3 domain
+///
+/// # Safety
+///
+/// Only implemented for regions the BDMA can access
+pub unsafe trait BdmaReachable: Region {}
+
+/// DTCM, default RAM of the application, `.bss.dtcm`
+pub struct Dtcm;
+/// AXI SRAM, `.axisram`
+pub struct AxiSram;
+/// D2 SRAM1, `.sram1`
+pub struct Sram1;
+/// D2 SRAM2, `.sram2`
+pub struct Sram2;
+/// D2 SRAM3, `.sram3`
+pub struct Sram3;
+/// D3 SRAM4, `.sram4`
+pub struct Sram4;
+/// Backup SRAM, `.bsram`
+pub struct Bsram;
+/// FMC SDRAM, `.sdram`
+pub struct Sdram;
+
+impl Region for Dtcm {}
+impl Region for AxiSram {}
+impl Region for Sram1 {
+    fn enable() {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        rcc.ahb2enr.modify(|_, w| w.sram1en().set_bit());
+    }
+}
+impl Region for Sram2 {
+    fn enable() {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        rcc.ahb2enr.modify(|_, w| w.sram2en().set_bit());
+    }
+}
+impl Region for Sram3 {
+    fn enable() {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        rcc.ahb2enr.modify(|_, w| w.sram3en().set_bit());
+    }
+}
+impl Region for Sram4 {}
+impl Region for Bsram {
+    fn enable() {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        rcc.ahb4enr.modify(|_, w| w.bkpramen().set_bit());
+    }
+}
+
+impl Region for Sdram {
+    fn enable() {
+        assert!(
+            crate::board::Sdram::is_initialized(),
+            "SDRAM not initialized"
+        );
+    }
+}
+
+unsafe impl DmaReachable for AxiSram {}
+unsafe impl DmaReachable for Sram1 {}
+unsafe impl DmaReachable for Sram2 {}
+unsafe impl DmaReachable for Sram3 {}
+unsafe impl DmaReachable for Sram4 {}
+unsafe impl DmaReachable for Sdram {}
+unsafe impl BdmaReachable for Sram4 {}
+
+/// Type valid when all its bytes are zero
+///
+/// # Safety
+///
+/// Only implemented for types for which the all-zero bit pattern is valid
+pub unsafe trait Zeroable {}
+
+macro_rules! impl_zeroable {
+    ($($ty:ty),*) => {
+        $(unsafe impl Zeroable for $ty {})*
+    };
+}
+
+impl_zeroable!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+unsafe impl<T> Zeroable for MaybeUninit<T> {}
+
+/// Hands out the placed static once, used by [`ram_static!`](crate::ram_static)
+///
+/// # Safety
+///
+/// `cell` must point to a static only handed out through `taken`
+#[doc(hidden)]
+pub unsafe fn take<R: Region, T>(
+    taken: &AtomicBool,
+    cell: *mut MaybeUninit<T>,
+) -> &'static mut MaybeUninit<T> {
+    assert!(
+        !taken.swap(true, Ordering::AcqRel),
+        "placed static already taken"
+    );
+    R::enable();
+    &mut *cell
+}
+
+#[doc(hidden)]
+pub fn zeroed<T: Zeroable>(cell: &'static mut MaybeUninit<T>) -> &'static mut T {
+    unsafe {
+        cell.as_mut_ptr().write_bytes(0, 1);
+        cell.assume_init_mut()
+    }
+}
+
+#[doc(hidden)]
+pub const fn assert_dma_reachable<R: DmaReachable>() {}
+
+/// Places a static in a RAM region and returns a `&'static mut` to it, written with
+/// a value or zeroed. Each invocation can only be executed once.
+///
+/// ```ignore
+/// let buffer: &'static mut [u8; 512] = ram_static!(Sram1, [u8; 512] = [0xFF; 512]);
+/// let samples: &'static mut [u16; 1024] = ram_static!(AxiSram, [u16; 1024], zeroed);
+/// ```
+#[macro_export]
+macro_rules! ram_static {
+    (Dtcm, $($t:tt)*) => { $crate::__ram_static!(".bss.dtcm", Dtcm, $($t)*) };
+    (AxiSram, $($t:tt)*) => { $crate::__ram_static!(".axisram", AxiSram, $($t)*) };
+    (Sram1, $($t:tt)*) => { $crate::__ram_static!(".sram1", Sram1, $($t)*) };
+    (Sram2, $($t:tt)*) => { $crate::__ram_static!(".sram2", Sram2, $($t)*) };
+    (Sram3, $($t:tt)*) => { $crate::__ram_static!(".sram3", Sram3, $($t)*) };
+    (Sram4, $($t:tt)*) => { $crate::__ram_static!(".sram4", Sram4, $($t)*) };
+    (Bsram, $($t:tt)*) => { $crate::__ram_static!(".bsram", Bsram, $($t)*) };
+    (Sdram, $($t:tt)*) => { $crate::__ram_static!(".sdram", Sdram, $($t)*) };
+}
+
+/// [`ram_static!`](crate::ram_static) failing to compile if the region is not
+/// reachable by DMA
+///
+/// ```ignore
+/// let usb_buffer = dma_static!(AxiSram, [u32; 1024], zeroed);
+/// ```
+#[macro_export]
+macro_rules! dma_static {
+    ($region:ident, $($t:tt)*) => {{
+        $crate::sys::ram::assert_dma_reachable::<$crate::sys::ram::$region>();
+        $crate::ram_static!($region, $($t)*)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ram_static {
+    (@cell $section:literal, $region:ident, $ty:ty) => {{
+        #[link_section = $section]
+        static mut CELL: ::core::mem::MaybeUninit<$ty> = ::core::mem::MaybeUninit::uninit();
+        static TAKEN: ::core::sync::atomic::AtomicBool =
+            ::core::sync::atomic::AtomicBool::new(false);
+        unsafe {
+            $crate::sys::ram::take::<$crate::sys::ram::$region, $ty>(
+                &TAKEN,
+                ::core::ptr::addr_of_mut!(CELL),
+            )
+        }
+    }};
+    ($section:literal, $region:ident, $ty:ty = $value:expr) => {{
+        let cell = $crate::__ram_static!(@cell $section, $region, $ty);
+        // Typed binding, so that an expected unsized type does not drive inference
+        let value: &'static mut $ty = cell.write($value);
+        value
+    }};
+    ($section:literal, $region:ident, $ty:ty, zeroed) => {{
+        let cell = $crate::__ram_static!(@cell $section, $region, $ty);
+        let value: &'static mut $ty = $crate::sys::ram::zeroed(cell);
+        value
+    }};
+}
@@ -0,0 +1,458 @@
+//! report
+//!
+//! Decoding of the RCC registers into the clocks the board actually runs with
+//!
+
+use fugit::HertzU32;
+
+const CSI_FREQUENCY: u32 = 4_000_000;
+const HSI48_FREQUENCY: u32 = 48_000_000;
+const LSE_FREQUENCY: u32 = 32_768;
+
+/// Raw RCC register values, see [`RccSnapshot::capture`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct RccSnapshot {
+    pub cr: u32,
+    pub cfgr: u32,
+    pub d1cfgr: u32,
+    pub d2cfgr: u32,
+    pub d3cfgr: u32,
+    pub pllckselr: u32,
+    pub pllcfgr: u32,
+    pub pll1divr: u32,
+    pub pll1fracr: u32,
+    pub pll2divr: u32,
+    pub pll2fracr: u32,
+    pub pll3divr: u32,
+    pub pll3fracr: u32,
+    pub d1ccipr: u32,
+    pub d2ccip1r: u32,
+    pub d2ccip2r: u32,
+    pub d3ccipr: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SysClkSource {
+    Hsi,
+    Csi,
+    Hse,
+    Pll1,
+}
+
+/// Outputs of a PLL, `None` when the PLL or the output is disabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct PllOutputs {
+    pub vco: Option<HertzU32>,
+    pub p: Option<HertzU32>,
+    pub q: Option<HertzU32>,
+    pub r: Option<HertzU32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum KernelSource {
+    Disabled,
+    Hclk,
+    Pclk1,
+    Pclk2,
+    Pclk4,
+    Pll1Q,
+    Pll2P,
+    Pll2Q,
+    Pll2R,
+    Pll3P,
+    Pll3Q,
+    Pll3R,
+    PerCk,
+    Hsi,
+    Csi,
+    Hse,
+    Hsi48,
+    Lse,
+    /// External clock input, frequency unknown
+    External,
+    Reserved,
+}
+
+/// Kernel clock of a peripheral, `frequency` is `None` when disabled or unknown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct KernelClock {
+    pub source: KernelSource,
+    pub frequency: Option<HertzU32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct KernelClocks {
+    pub per_ck: KernelClock,
+    pub fmc: KernelClock,
+    pub qspi: KernelClock,
+    pub sdmmc: KernelClock,
+    pub spi123: KernelClock,
+    pub spi45: KernelClock,
+    pub usart16: KernelClock,
+    pub usart234578: KernelClock,
+    pub i2c123: KernelClock,
+    pub i2c4: KernelClock,
+    pub fdcan: KernelClock,
+    pub rng: KernelClock,
+    pub usb: KernelClock,
+    pub adc: KernelClock,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ClockReport {
+    pub sys_source: SysClkSource,
+    pub sys_ck: HertzU32,
+    pub c_ck: HertzU32,
+    pub hclk: HertzU32,
+    pub pclk1: HertzU32,
+    pub pclk2: HertzU32,
+    pub pclk3: HertzU32,
+    pub pclk4: HertzU32,
+    /// Kernel clock of the APB1 timers
+    pub timx_ker_ck: HertzU32,
+    /// Kernel clock of the APB2 timers
+    pub timy_ker_ck: HertzU32,
+    /// Reference of every PLL, `None` when no source is selected
+    pub pll_source: Option<SysClkSource>,
+    pub pll1: PllOutputs,
+    pub pll2: PllOutputs,
+    pub pll3: PllOutputs,
+    pub kernel: KernelClocks,
+}
+
+const fn field(reg: u32, offset: u32, width: u32) -> u32 {
+    (reg >> offset) & ((1 << width) - 1)
+}
+
+const fn bit(reg: u32, offset: u32) -> bool {
+    field(reg, offset, 1) != 0
+}
+
+/// Divider of HPRE and D1CPRE
+const fn ahb_divider(bits: u32) -> u32 {
+    match bits {
+        0b1000..=0b1011 => 1 << (bits - 0b0111),
+        0b1100..=0b1111 => 1 << (bits - 0b0110),
+        _ => 1,
+    }
+}
+
+/// Divider of the APB prescalers
+const fn apb_divider(bits: u32) -> u32 {
+    match bits {
+        0b100..=0b111 => 1 << (bits - 0b011),
+        _ => 1,
+    }
+}
+
+fn hz(frequency: u32) -> Option<HertzU32> {
+    Some(HertzU32::from_raw(frequency))
+}
+
+impl ClockReport {
+    /// Decodes `snapshot`, `hse` being the frequency of the external oscillator
+    pub fn decode(snapshot: &RccSnapshot, hse: HertzU32) -> Self {
+        let s = snapshot;
+        let hsi = 64_000_000 >> field(s.cr, 3, 2);
+        let hse = hse.raw();
+
+        let source_frequency = |source| match source {
+            SysClkSource::Hsi => hsi,
+            SysClkSource::Csi => CSI_FREQUENCY,
+            SysClkSource::Hse => hse,
+            SysClkSource::Pll1 => 0,
+        };
+
+        // PLLs
+        let pll_source = match field(s.pllckselr, 0, 2) {
+            0 => Some(SysClkSource::Hsi),
+            1 => Some(SysClkSource::Csi),
+            2 => Some(SysClkSource::Hse),
+            _ => None,
+        };
+        let pll_ref = pll_source.map_or(0, source_frequency);
+        let pll = |index: u32, divr: u32, fracr: u32| {
+            let divm = field(s.pllckselr, 4 + 8 * index, 6);
+            let on = bit(s.cr, 24 + 2 * index);
+            if !on || divm == 0 || pll_ref == 0 {
+                return PllOutputs::default();
+            }
+            let divn = field(divr, 0, 9) + 1;
+            let fracn = if bit(s.pllcfgr, 4 * index) {
+                field(fracr, 3, 13)
+            } else {
+                0
+            };
+            // VCO = ref / DIVM * (DIVN + FRACN / 2^13)
+            let multiplier = (divn as u64) << 13 | fracn as u64;
+            let vco = ((pll_ref as u64 * multiplier) >> 13) / divm as u64;
+            let vco = vco as u32;
+            let output = |enable: u32, offset: u32| {
+                if bit(s.pllcfgr, 16 + 3 * index + enable) {
+                    hz(vco / (field(divr, offset, 7) + 1))
+                } else {
+                    None
+                }
+            };
+            PllOutputs {
+                vco: hz(vco),
+                p: output(0, 9),
+                q: output(1, 16),
+                r: output(2, 24),
+            }
+        };
+        let pll1 = pll(0, s.pll1divr, s.pll1fracr);
+        let pll2 = pll(1, s.pll2divr, s.pll2fracr);
+        let pll3 = pll(2, s.pll3divr, s.pll3fracr);
+
+        // System and bus clocks
+        let sys_source = match field(s.cfgr, 3, 3) {
+            1 => SysClkSource::Csi,
+            2 => SysClkSource::Hse,
+            3 => SysClkSource::Pll1,
+            _ => SysClkSource::Hsi,
+        };
+        let sys_ck = match sys_source {
+            SysClkSource::Pll1 => pll1.p.map_or(0, |p| p.raw()),
+            source => source_frequency(source),
+        };
+        let c_ck = sys_ck / ahb_divider(field(s.d1cfgr, 8, 4));
+        let hclk = c_ck / ahb_divider(field(s.d1cfgr, 0, 4));
+        let ppre1 = apb_divider(field(s.d2cfgr, 4, 3));
+        let ppre2 = apb_divider(field(s.d2cfgr, 8, 3));
+        let pclk1 = hclk / ppre1;
+        let pclk2 = hclk / ppre2;
+        let pclk3 = hclk / apb_divider(field(s.d1cfgr, 4, 3));
+        let pclk4 = hclk / apb_divider(field(s.d3cfgr, 4, 3));
+        let timpre = bit(s.cfgr, 15);
+        let timer = |pclk: u32, ppre: u32| match (timpre, ppre) {
+            (_, 1) => pclk,
+            (false, _) => pclk * 2,
+            (true, 2 | 4) => hclk,
+            (true, _) => pclk * 4,
+        };
+
+        // Kernel clocks
+        let resolve = |source: KernelSource| {
+            let frequency = match source {
+                KernelSource::Hclk => hz(hclk),
+                KernelSource::Pclk1 => hz(pclk1),
+                KernelSource::Pclk2 => hz(pclk2),
+                KernelSource::Pclk4 => hz(pclk4),
+                KernelSource::Pll1Q => pll1.q,
+                KernelSource::Pll2P => pll2.p,
+                KernelSource::Pll2Q => pll2.q,
+                KernelSource::Pll2R => pll2.r,
+                KernelSource::Pll3P => pll3.p,
+                KernelSource::Pll3Q => pll3.q,
+                KernelSource::Pll3R => pll3.r,
+                KernelSource::Hsi => hz(hsi),
+                KernelSource::Csi => hz(CSI_FREQUENCY),
+                KernelSource::Hse => hz(hse),
+                KernelSource::Hsi48 => hz(HSI48_FREQUENCY),
+                KernelSource::Lse => hz(LSE_FREQUENCY),
+                KernelSource::PerCk
+                | KernelSource::Disabled
+                | KernelSource::External
+                | KernelSource::Reserved => None,
+            };
+            KernelClock { source, frequency }
+        };
+        use KernelSource::*;
+        let per_ck = resolve(match field(s.d1ccipr, 28, 2) {
+            0 => Hsi,
+            1 => Csi,
+            2 => Hse,
+            _ => Disabled,
+        });
+        let mux = |sources: &[KernelSource], bits: u32| {
+            let source = sources.get(bits as usize).copied().unwrap_or(Reserved);
+            match source {
+                PerCk => KernelClock {
+                    source,
+                    frequency: per_ck.frequency,
+                },
+                source => resolve(source),
+            }
+        };
+        let kernel = KernelClocks {
+            per_ck,
+            fmc: mux(&[Hclk, Pll1Q, Pll2R, PerCk], field(s.d1ccipr, 0, 2)),
+            qspi: mux(&[Hclk, Pll1Q, Pll2R, PerCk], field(s.d1ccipr, 4, 2)),
+            sdmmc: mux(&[Pll1Q, Pll2R], field(s.d1ccipr, 16, 1)),
+            spi123: mux(
+                &[Pll1Q, Pll2P, Pll3P, External, PerCk],
+                field(s.d2ccip1r, 12, 3),
+            ),
+            spi45: mux(
+                &[Pclk2, Pll2Q, Pll3Q, Hsi, Csi, Hse],
+                field(s.d2ccip1r, 16, 3),
+            ),
+            fdcan: mux(&[Hse, Pll1Q, Pll2Q], field(s.d2ccip1r, 28, 2)),
+            usart16: mux(
+                &[Pclk2, Pll2Q, Pll3Q, Hsi, Csi, Lse],
+                field(s.d2ccip2r, 3, 3),
+            ),
+            usart234578: mux(
+                &[Pclk1, Pll2Q, Pll3Q, Hsi, Csi, Lse],
+                field(s.d2ccip2r, 0, 3),
+            ),
+            rng: mux(&[Hsi48, Pll1Q, Lse, Disabled], field(s.d2ccip2r, 8, 2)),
+            i2c123: mux(&[Pclk1, Pll3R, Hsi, Csi], field(s.d2ccip2r, 12, 2)),
+            usb: mux(&[Disabled, Pll1Q, Pll3Q, Hsi48], field(s.d2ccip2r, 20, 2)),
+            i2c4: mux(&[Pclk4, Pll3R, Hsi, Csi], field(s.d3ccipr, 8, 2)),
+            adc: mux(&[Pll2P, Pll3R, PerCk], field(s.d3ccipr, 16, 2)),
+        };
+
+        Self {
+            sys_source,
+            sys_ck: HertzU32::from_raw(sys_ck),
+            c_ck: HertzU32::from_raw(c_ck),
+            hclk: HertzU32::from_raw(hclk),
+            pclk1: HertzU32::from_raw(pclk1),
+            pclk2: HertzU32::from_raw(pclk2),
+            pclk3: HertzU32::from_raw(pclk3),
+            pclk4: HertzU32::from_raw(pclk4),
+            timx_ker_ck: HertzU32::from_raw(timer(pclk1, ppre1)),
+            timy_ker_ck: HertzU32::from_raw(timer(pclk2, ppre2)),
+            pll_source,
+            pll1,
+            pll2,
+            pll3,
+            kernel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HSE: HertzU32 = HertzU32::from_raw(25_000_000);
+
+    fn mhz(frequency: u32) -> HertzU32 {
+        HertzU32::from_raw(frequency * 1_000_000)
+    }
+
+    /// Reset values of RM0399
+    const RESET: RccSnapshot = RccSnapshot {
+        cr: 0x0000_0025,
+        cfgr: 0,
+        d1cfgr: 0,
+        d2cfgr: 0,
+        d3cfgr: 0,
+        pllckselr: 0x0202_0200,
+        pllcfgr: 0x01FF_0000,
+        pll1divr: 0x0101_0280,
+        pll1fracr: 0,
+        pll2divr: 0x0101_0280,
+        pll2fracr: 0,
+        pll3divr: 0x0101_0280,
+        pll3fracr: 0,
+        d1ccipr: 0,
+        d2ccip1r: 0,
+        d2ccip2r: 0,
+        d3ccipr: 0,
+    };
+
+    #[test]
+    fn hsi_reset() {
+        let report = ClockReport::decode(&RESET, HSE);
+        assert_eq!(report.sys_source, SysClkSource::Hsi);
+        for clock in [
+            report.sys_ck,
+            report.c_ck,
+            report.hclk,
+            report.pclk1,
+            report.pclk2,
+            report.pclk3,
+            report.pclk4,
+            report.timx_ker_ck,
+            report.timy_ker_ck,
+        ] {
+            assert_eq!(clock, mhz(64));
+        }
+        assert_eq!(report.pll_source, Some(SysClkSource::Hsi));
+        // PLLs off
+        assert_eq!(report.pll1, PllOutputs::default());
+        assert_eq!(report.pll2, PllOutputs::default());
+        assert_eq!(report.pll3, PllOutputs::default());
+        let kernel = report.kernel;
+        assert_eq!(kernel.per_ck.frequency, Some(mhz(64)));
+        assert_eq!(kernel.fmc.source, KernelSource::Hclk);
+        assert_eq!(kernel.i2c123.frequency, Some(mhz(64)));
+        assert_eq!(kernel.rng.frequency, Some(mhz(48)));
+        assert_eq!(kernel.usb.source, KernelSource::Disabled);
+        assert_eq!(kernel.adc.frequency, None);
+    }
+
+    #[test]
+    fn hse_pll1_480_mhz() {
+        let snapshot = RccSnapshot {
+            // HSE bypassed and PLL1 on and ready, HSI on
+            cr: 0x0307_0005,
+            // PLL1 selected and used
+            cfgr: 0x1B,
+            // HPRE and D1PPRE / 2
+            d1cfgr: 0x48,
+            // D2PPRE1 and D2PPRE2 / 2
+            d2cfgr: 0x440,
+            // D3PPRE / 2
+            d3cfgr: 0x40,
+            // HSE / 5
+            pllckselr: 0x52,
+            // PLL1 P and Q outputs
+            pllcfgr: 0x0003_0000,
+            // N = 192, P = 2, Q = 8, R = 2
+            pll1divr: 0x0107_02BF,
+            d2ccip2r: 1 << 20,
+            ..RESET
+        };
+        let report = ClockReport::decode(&snapshot, HSE);
+        assert_eq!(report.sys_source, SysClkSource::Pll1);
+        assert_eq!(report.pll_source, Some(SysClkSource::Hse));
+        assert_eq!(
+            report.pll1,
+            PllOutputs {
+                vco: Some(mhz(960)),
+                p: Some(mhz(480)),
+                q: Some(mhz(120)),
+                r: None,
+            }
+        );
+        assert_eq!(report.pll2, PllOutputs::default());
+        assert_eq!(report.sys_ck, mhz(480));
+        assert_eq!(report.c_ck, mhz(480));
+        assert_eq!(report.hclk, mhz(240));
+        for pclk in [report.pclk1, report.pclk2, report.pclk3, report.pclk4] {
+            assert_eq!(pclk, mhz(120));
+        }
+        assert_eq!(report.timx_ker_ck, mhz(240));
+        assert_eq!(report.timy_ker_ck, mhz(240));
+        assert_eq!(report.kernel.qspi.frequency, Some(mhz(240)));
+        assert_eq!(report.kernel.usb.frequency, Some(mhz(120)));
+        assert_eq!(report.kernel.i2c123.frequency, Some(mhz(120)));
+        assert_eq!(report.kernel.per_ck.frequency, Some(mhz(64)));
+    }
+
+    #[test]
+    fn fractional_pll_and_timpre() {
+        let snapshot = RccSnapshot {
+            cr: 0x0307_0005,
+            cfgr: 0x1B | 1 << 15,
+            d2cfgr: 0x440,
+            pllckselr: 0x52,
+            // PLL1 fractional, P output
+            pllcfgr: 0x0001_0001,
+            // N = 80 + 4096 / 8192, P = 2
+            pll1divr: 0x0101_024F,
+            pll1fracr: 4096 << 3,
+            ..RESET
+        };
+        let report = ClockReport::decode(&snapshot, HSE);
+        assert_eq!(report.pll1.vco, Some(HertzU32::from_raw(402_500_000)));
+        assert_eq!(report.sys_ck, HertzU32::from_raw(201_250_000));
+        // TIMPRE with APB / 2, timers on hclk
+        assert_eq!(report.timx_ker_ck, report.hclk);
+    }
+}
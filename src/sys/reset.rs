@@ -0,0 +1,100 @@
+//! reset
+//!
+//! Cause of the last reset, from the RCC_RSR flags
+//!
+
+use stm32h7xx_hal::pac;
+
+const CPURSTF: u32 = 1 << 17;
+const D1RSTF: u32 = 1 << 19;
+const D2RSTF: u32 = 1 << 20;
+const BORRSTF: u32 = 1 << 21;
+const PINRSTF: u32 = 1 << 22;
+const PORRSTF: u32 = 1 << 23;
+const SFT1RSTF: u32 = 1 << 24;
+const SFT2RSTF: u32 = 1 << 25;
+const IWDG1RSTF: u32 = 1 << 26;
+const IWDG2RSTF: u32 = 1 << 27;
+const WWDG1RSTF: u32 = 1 << 28;
+const WWDG2RSTF: u32 = 1 << 29;
+const LPWR1RSTF: u32 = 1 << 30;
+const LPWR2RSTF: u32 = 1 << 31;
+
+/// Core whose action caused the reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Core {
+    Cm7,
+    Cm4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ResetReason {
+    PowerOn,
+    Brownout,
+    /// NRST pin, e.g. the reset button and its double tap entering the bootloader
+    Pin,
+    /// `SCB::sys_reset` or a panic resetting
+    Software(Core),
+    IndependentWatchdog(Core),
+    WindowWatchdog(Core),
+    /// Illegal entry in Stop or Standby mode
+    LowPower(Core),
+    /// D1 domain exiting DStandby
+    D1Standby,
+    /// D2 domain exiting DStandby
+    D2Standby,
+    /// CPU reset alone
+    Cpu,
+    /// RCC_RSR value without a known flag combination
+    Unknown(u32),
+}
+
+impl ResetReason {
+    /// Decodes the RCC_RSR flags, where a reset also raises the flags of the resets it
+    /// goes through, e.g. the pin flag for every system reset
+    pub fn from_rsr(rsr: u32) -> Self {
+        let flag = |mask: u32| rsr & mask != 0;
+        if flag(PORRSTF) {
+            Self::PowerOn
+        } else if flag(BORRSTF) {
+            Self::Brownout
+        } else if flag(IWDG1RSTF) {
+            Self::IndependentWatchdog(Core::Cm7)
+        } else if flag(IWDG2RSTF) {
+            Self::IndependentWatchdog(Core::Cm4)
+        } else if flag(WWDG1RSTF) {
+            Self::WindowWatchdog(Core::Cm7)
+        } else if flag(WWDG2RSTF) {
+            Self::WindowWatchdog(Core::Cm4)
+        } else if flag(LPWR1RSTF) {
+            Self::LowPower(Core::Cm7)
+        } else if flag(LPWR2RSTF) {
+            Self::LowPower(Core::Cm4)
+        } else if flag(SFT1RSTF) {
+            Self::Software(Core::Cm7)
+        } else if flag(SFT2RSTF) {
+            Self::Software(Core::Cm4)
+        } else if flag(PINRSTF) {
+            Self::Pin
+        } else if flag(D1RSTF) {
+            Self::D1Standby
+        } else if flag(D2RSTF) {
+            Self::D2Standby
+        } else if flag(CPURSTF) {
+            Self::Cpu
+        } else {
+            Self::Unknown(rsr)
+        }
+    }
+
+    /// Reads the reset flags and clears them, so the next reset reports only its own
+    ///
+    /// Done once at board setup, see `Board::reset_reason`
+    pub fn take() -> Self {
+        let rcc = unsafe { &(*pac::RCC::ptr()) };
+        let rsr = rcc.rsr.read().bits();
+        rcc.rsr.modify(|_, w| w.rmvf().set_bit());
+        rcc.rsr.modify(|_, w| w.rmvf().clear_bit());
+        Self::from_rsr(rsr)
+    }
+}